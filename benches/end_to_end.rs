@@ -0,0 +1,86 @@
+//! Runs of generated files of 1M and 10M rows end to end, reporting the rows
+//! per second and the peak memory of each. Files are generated once under
+//! `target/bench-data`. `BENCH_ROWS=1000000,5000000` runs other sizes.
+
+use std::{
+    env, fs,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use bulbasaur::{
+    app,
+    cli_config::CliConfig,
+    generator::{self, Settings},
+};
+
+const SIZES: [u64; 2] = [1_000_000, 10_000_000];
+
+/// data returns the file of `rows` generated rows, generating it first if
+/// needed.
+fn data(rows: u64) -> PathBuf {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/bench-data");
+    let path = dir.join(format!("{}.csv", rows));
+    if !path.exists() {
+        fs::create_dir_all(&dir).unwrap();
+        let settings = Settings {
+            rows,
+            seed: 42,
+            clients: 10_000,
+            ..Default::default()
+        };
+        let file = BufWriter::new(fs::File::create(&path).unwrap());
+        generator::generate(&settings, file).unwrap();
+    }
+    path
+}
+
+/// reset_peak forgets the peak memory of the process so far. Only Linux
+/// keeps it, elsewhere no peak is reported.
+fn reset_peak() {
+    let _ = fs::write("/proc/self/clear_refs", "5");
+}
+
+/// peak returns the peak resident memory of the process in MiB.
+fn peak() -> Option<f64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: f64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib / 1024.0)
+}
+
+fn main() {
+    let sizes: Vec<u64> = match env::var("BENCH_ROWS") {
+        Ok(sizes) => sizes.split(',').map(|size| size.parse().unwrap()).collect(),
+        Err(_) => SIZES.to_vec(),
+    };
+    // cargo bench passes --bench, and filters that do not apply here
+    if env::args().any(|arg| arg == "--list") {
+        return;
+    }
+
+    for rows in sizes {
+        let path = data(rows);
+        let config =
+            CliConfig::new(&["bulbasaur".to_string(), path.display().to_string()]).unwrap();
+
+        reset_peak();
+        let start = Instant::now();
+        // as the binary runs, but for the accounts it prints
+        app::run(config, io::sink()).unwrap();
+        let elapsed = start.elapsed();
+
+        let peak = match peak() {
+            Some(peak) => format!("{:.1} MiB", peak),
+            None => "unknown".to_string(),
+        };
+        println!(
+            "end_to_end/{}: {:.2?}, {:.0} rows/s, peak memory {}",
+            rows,
+            elapsed,
+            rows as f64 / elapsed.as_secs_f64(),
+            peak
+        );
+    }
+}
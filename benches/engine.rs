@@ -0,0 +1,60 @@
+//! Throughput of parsing rows and of `process_txn` for each kind of
+//! transaction, in rows per second.
+
+use std::hint::black_box;
+
+use bulbasaur::{
+    app::bench,
+    generator::{self, Settings},
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+/// transactions processed by an iteration of the kind benchmarks
+const COUNT: u32 = 10_000;
+
+fn parsing(c: &mut Criterion) {
+    let settings = Settings {
+        rows: 100_000,
+        ..Default::default()
+    };
+    let mut rows = vec![];
+    generator::generate(&settings, &mut rows).unwrap();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(settings.rows));
+    group.bench_function("generated", |b| {
+        b.iter(|| bench::parse(black_box(rows.as_slice())).unwrap())
+    });
+    group.finish();
+}
+
+fn kinds(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_txn");
+    group.throughput(Throughput::Elements(COUNT as u64));
+    for kind in [
+        "deposit",
+        "withdrawal",
+        "transfer",
+        "refund",
+        "exchange",
+        "authorize",
+        "capture",
+        "void",
+        "dispute",
+        "resolve",
+        "chargeback",
+    ] {
+        let workload = bench::workload(kind, COUNT);
+        group.bench_function(kind, |b| {
+            b.iter_batched(
+                || workload.prepare(),
+                |batch| batch.process(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parsing, kinds);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+//! Raw bytes read as a transactions file by an engine configured with the
+//! files of `fuzz/config`, so that fees, credit lines, exchange rates, risk
+//! rules, heuristics, dispute windows and admin transactions are reached.
+
+#![no_main]
+
+use bulbasaur::{app, cli_config::CliConfig};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let file = |name: &str| format!("{}/config/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let args = [
+        "bulbasaur".to_string(),
+        "input.csv".to_string(),
+        "--fees".to_string(),
+        file("fees.csv"),
+        "--clients".to_string(),
+        file("clients.csv"),
+        "--rates".to_string(),
+        file("rates.csv"),
+        "--rules".to_string(),
+        file("rules.csv"),
+        "--heuristics".to_string(),
+        file("heuristics.csv"),
+        "--dispute-windows".to_string(),
+        file("dispute_windows.csv"),
+        "--operators".to_string(),
+        file("operators.csv"),
+        "--reservation".to_string(),
+        "strict".to_string(),
+        "--as-of".to_string(),
+        "2023-01-01T00:00:00Z".to_string(),
+    ];
+    let config = CliConfig::new(&args).unwrap();
+    if let Err(err) = app::check(&config, data) {
+        panic!("{}", err);
+    }
+});
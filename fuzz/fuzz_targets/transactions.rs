@@ -0,0 +1,15 @@
+//! Raw bytes read as a transactions file by an engine without any
+//! configuration. Nothing may panic and every invariant must hold.
+
+#![no_main]
+
+use bulbasaur::{app, cli_config::CliConfig};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let args = ["bulbasaur".to_string(), "input.csv".to_string()];
+    let config = CliConfig::new(&args).unwrap();
+    if let Err(err) = app::check(&config, data) {
+        panic!("{}", err);
+    }
+});
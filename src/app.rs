@@ -1,665 +1,6558 @@
-use std::{collections::HashMap, error::Error};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error::Error,
+    fmt, io,
+    str::FromStr,
+};
 
-use crate::cli_config::CliConfig;
+use crate::cli_config::{CliConfig, Command};
+use crate::clients::{self, ClientConfig};
+use crate::currency::{self, round};
+use crate::disputes::DisputeWindows;
+use crate::exchange::{self, RateTable, Rounding};
+use crate::fees::FeeSchedule;
+use crate::generator;
+use crate::heuristics::{Action, Flag, Monitor};
+use crate::ledger::{
+    Entry, JournalRow, Ledger,
+    LedgerAccount::{Available, Exchange, ExchangeSpread, External, Fees, Held},
+};
+use crate::operators;
+use crate::reconcile;
+use crate::rules::RuleSet;
+use crate::statements::{self, Format, Statement, Statements, Totals};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeDelta};
 
 use serde::{Deserialize, Serialize};
 
 use csv::Trim;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// longest authorization lifetime that can be configured, about a hundred
+/// years
+const MAX_AUTHORIZATION_DAYS: i64 = 36_500;
+
+/// largest amount a transaction or an opening balance can have, to turn away
+/// amounts no account could hold. It does not make the amounts under it
+/// exact: f32 no longer keeps cents above about 100000, nor fractions at all
+/// above about 1e7.
+const MAX_AMOUNT: f32 = 1e12;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 struct Transaction {
     r#type: String,
     client: u16,
     amount: Option<f32>,
     tx: u32,
+    /// receiving client of a transfer
+    #[serde(default)]
+    destination: Option<u16>,
+    /// original deposit of a refund
+    #[serde(default)]
+    reference: Option<u32>,
+    /// ISO 4217 code of the amount, rows without one use a currency-less
+    /// balance with four digit precision
+    #[serde(default)]
+    currency: Option<String>,
+    /// currency an exchange converts into
+    #[serde(default)]
+    to_currency: Option<String>,
+    /// operator sending an admin transaction
+    #[serde(default)]
+    operator: Option<String>,
+    /// why an operator sent an admin transaction
+    #[serde(default)]
+    reason: Option<String>,
+    /// RFC 3339 time the transaction happened at
+    #[serde(default)]
+    timestamp: Option<DateTime<FixedOffset>>,
 }
 
 #[derive(Debug)]
 struct Record {
     txn: Transaction,
-    has_dispute: bool,
+    /// part of the transaction currently under dispute
+    disputed: f32,
+    /// part of the transaction that was charged back
+    charged_back: f32,
+    /// part of a deposit that was refunded
+    refunded: f32,
+    /// fee charged on the transaction
+    fee: f32,
+    /// part of the fee that went back to the client on a chargeback
+    fee_refunded: f32,
+    /// part of an authorization still waiting to be captured, voided or to
+    /// expire
+    hold: f32,
+    /// part of an authorization that was captured
+    captured: f32,
+    /// time the open dispute of the transaction was opened at
+    disputed_at: Option<DateTime<FixedOffset>>,
+}
+
+impl Record {
+    fn new(txn: Transaction) -> Self {
+        let hold = match (txn.r#type.as_str(), txn.amount) {
+            ("authorize", Some(amount)) => amount,
+            _ => 0.0,
+        };
+        Record {
+            txn,
+            disputed: 0.0,
+            charged_back: 0.0,
+            refunded: 0.0,
+            fee: 0.0,
+            fee_refunded: 0.0,
+            hold,
+            captured: 0.0,
+            disputed_at: None,
+        }
+    }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 struct Stats {
     client: u16,
+    /// balances by currency, `None` holds the amounts without a currency
+    balances: BTreeMap<Option<String>, Balance>,
+    locked: bool,
+    /// set when a heuristic found the client suspicious
+    flagged: bool,
+    /// a closed account takes no further transactions
+    closed: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Balance {
+    available: f32,
+    held: f32,
+    total: f32,
+}
+
+impl Stats {
+    fn new(client: u16) -> Self {
+        Stats {
+            client,
+            balances: BTreeMap::new(),
+            locked: false,
+            flagged: false,
+            closed: false,
+        }
+    }
+
+    /// balance returns the balance in `currency`, zero if the client never
+    /// held any.
+    fn balance(&self, currency: &Option<String>) -> Balance {
+        self.balances.get(currency).copied().unwrap_or_default()
+    }
+}
+
+/// Account is a row of the output, one for each currency a client holds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Account {
+    pub client: u16,
+    #[serde(default)]
+    pub currency: Option<String>,
+    pub available: f32,
+    pub held: f32,
+    pub total: f32,
+    pub locked: bool,
+    #[serde(default)]
+    pub flagged: bool,
+    #[serde(default)]
+    pub closed: bool,
+    /// part of the client's credit line in use, derived from the balance
+    #[serde(default)]
+    pub credit_used: f32,
+}
+
+/// Step is what a transaction did to the client being inspected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub tx: u32,
+    pub r#type: String,
+    /// why the transaction was not applied
+    pub rejection: Option<String>,
+    /// balances of the client after the transaction, the ones it changed or
+    /// else the one in the currency of the transaction
+    pub accounts: Vec<Account>,
+}
+
+/// InspectRow is a row of the inspect output, one for each balance of a step.
+#[derive(Serialize, Debug)]
+struct InspectRow<'a> {
+    tx: u32,
+    r#type: &'a str,
+    currency: &'a Option<String>,
     available: f32,
     held: f32,
     total: f32,
     locked: bool,
+    reason: &'a Option<String>,
+}
+
+/// House keeps what the engine earns for itself, by currency.
+#[derive(Default, Debug)]
+struct House {
+    exchange_spread: BTreeMap<Option<String>, f32>,
+    fees: BTreeMap<Option<String>, f32>,
+}
+
+/// HouseAccount is a row of the house accounts output.
+#[derive(Serialize, Debug)]
+struct HouseAccount<'a> {
+    account: &'a str,
+    currency: &'a Option<String>,
+    amount: f32,
+}
+
+/// Reservation decides how held funds count towards what a client can spend.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum Reservation {
+    /// held funds are reserved, only available funds can be spent
+    #[default]
+    Available,
+    /// like `Available`, and funds given back while a debit is disputed
+    /// cannot be spent either
+    Strict,
+    /// held funds can be spent as well
+    Total,
+}
+
+impl FromStr for Reservation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "available" => Ok(Reservation::Available),
+            "strict" => Ok(Reservation::Strict),
+            "total" => Ok(Reservation::Total),
+            _ => Err(format!(
+                "unknown reservation {}, expected available, strict or total",
+                s
+            )),
+        }
+    }
+}
+
+/// OutOfOrder decides what happens to a transaction older than the latest
+/// one of the same client.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum OutOfOrder {
+    #[default]
+    Accept,
+    Reject,
+}
+
+impl FromStr for OutOfOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(OutOfOrder::Accept),
+            "reject" => Ok(OutOfOrder::Reject),
+            _ => Err(format!(
+                "unknown out of order {}, expected accept or reject",
+                s
+            )),
+        }
+    }
+}
+
+/// Rejection is the reason a transaction was not applied.
+#[derive(Clone, Debug, PartialEq)]
+enum Rejection {
+    UnknownCurrency,
+    UnknownType,
+    UnknownClient,
+    UnknownTransaction,
+    InvalidAmount,
+    InvalidDestination,
+    InsufficientFunds,
+    AccountLocked,
+    AccountClosed,
+    /// closing an account that still holds funds
+    BalanceNotZero,
+    /// an admin transaction from an operator that is not allowed to send it
+    Unauthorized,
+    MissingReason,
+    /// a dispute opened after the dispute window of the transaction
+    DisputeExpired,
+    /// a transaction timestamped before an earlier one of the same client
+    OutOfOrder,
+    /// a transaction timestamped after the as-of time
+    AfterAsOf,
+    NotDisputed,
+    NoRate,
+    /// amounts too large for f32 to post to the ledger exactly
+    Unbalanced,
+    /// a risk rule of the rules file, with the rule that was broken
+    Rule(String),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::UnknownCurrency => write!(f, "unknown currency"),
+            Rejection::UnknownType => write!(f, "unknown transaction type"),
+            Rejection::UnknownClient => write!(f, "unknown client"),
+            Rejection::UnknownTransaction => write!(f, "unknown transaction"),
+            Rejection::InvalidAmount => write!(f, "invalid amount"),
+            Rejection::InvalidDestination => write!(f, "invalid destination"),
+            Rejection::InsufficientFunds => write!(f, "insufficient funds"),
+            Rejection::AccountLocked => write!(f, "account locked"),
+            Rejection::AccountClosed => write!(f, "account closed"),
+            Rejection::BalanceNotZero => write!(f, "balance not zero"),
+            Rejection::Unauthorized => write!(f, "operator not authorized"),
+            Rejection::MissingReason => write!(f, "missing reason"),
+            Rejection::DisputeExpired => write!(f, "dispute window expired"),
+            Rejection::OutOfOrder => write!(f, "out of order"),
+            Rejection::AfterAsOf => write!(f, "after as-of time"),
+            Rejection::NotDisputed => write!(f, "transaction not disputed"),
+            Rejection::NoRate => write!(f, "no exchange rate"),
+            Rejection::Unbalanced => write!(f, "amount cannot be kept exactly"),
+            Rejection::Rule(rule) => write!(f, "rule {}", rule),
+        }
+    }
+}
+
+/// RejectedTransaction is a row of the rejection report.
+#[derive(Serialize, Debug)]
+struct RejectedTransaction {
+    r#type: String,
+    client: u16,
+    tx: u32,
+    reason: String,
+}
+
+/// AdminAction is a row of the audit log, one for each applied admin
+/// transaction.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct AdminAction {
+    tx: u32,
+    client: u16,
+    /// unlock, freeze or close
+    action: String,
+    operator: String,
+    reason: String,
+}
+
+/// Engine holds everything needed to apply transactions one after another.
+#[derive(Default)]
+struct Engine {
+    records: Vec<Record>,
+    stats: HashMap<u16, Stats>,
+    house: House,
+    rates: RateTable,
+    /// date exchange rates are looked up for, the latest rates when unset
+    rates_date: Option<NaiveDate>,
+    exchange_rounding: Rounding,
+    fees: FeeSchedule,
+    clients: HashMap<u16, ClientConfig>,
+    reservation: Reservation,
+    rules: RuleSet,
+    /// operators allowed to send admin transactions
+    operators: HashSet<String>,
+    monitor: Monitor,
+    /// every heuristic that fired, in order
+    flags: Vec<Flag>,
+    /// every admin transaction applied, in order
+    audit: Vec<AdminAction>,
+    /// time the engine processes transactions at, later ones are not applied
+    as_of: Option<DateTime<FixedOffset>>,
+    out_of_order: OutOfOrder,
+    /// timestamp of the latest applied transaction by client
+    last_seen: HashMap<u16, DateTime<FixedOffset>>,
+    dispute_windows: DisputeWindows,
+    /// deadlines of open disputes with the disputed transaction
+    open_disputes: BTreeSet<(DateTime<FixedOffset>, u32)>,
+    /// how long an authorization holds its funds, for ever when `None`
+    authorization_lifetime: Option<TimeDelta>,
+    /// deadlines of authorizations with their transaction
+    open_authorizations: BTreeSet<(DateTime<FixedOffset>, u32)>,
+    /// every balance is posted here first, `stats` and `house` follow it
+    ledger: Ledger,
+    /// set when the invariants are checked after every transaction
+    invariants: Option<Invariants>,
+}
+
+/// Invariants keeps what the invariants are checked against.
+#[derive(Default, Debug)]
+struct Invariants {
+    /// what each recorded transaction holds, by client and currency
+    holds: HashMap<u32, Vec<(u16, Option<String>, f32)>>,
+    /// funds each account should hold for open disputes and authorizations
+    held: HashMap<(u16, Option<String>), f32>,
+}
+
+/// Watch is the state of the client sending a transaction before it applies.
+#[derive(Debug)]
+struct Watch {
+    tx: u32,
+    kind: String,
+    client: u16,
+    before: Option<Stats>,
+}
+
+/// load_engine sets up an engine with everything the config asks for.
+fn load_engine(config: &CliConfig) -> Result<Engine, Box<dyn Error>> {
+    let mut engine = Engine::default();
+
+    if let Some(filename) = &config.opening_balances {
+        load_opening_balances(std::fs::File::open(filename)?, &mut engine)?;
+    }
+    if let Some(filename) = &config.rates {
+        engine.rates = RateTable::load(std::fs::File::open(filename)?)?;
+    }
+    if let Some(date) = &config.rates_date {
+        engine.rates_date = Some(NaiveDate::parse_from_str(date, "%Y-%m-%d")?);
+    }
+    if let Some(rounding) = &config.exchange_rounding {
+        engine.exchange_rounding = rounding.parse()?;
+    }
+    if let Some(filename) = &config.fees {
+        engine.fees = FeeSchedule::load(std::fs::File::open(filename)?)?;
+    }
+    if let Some(filename) = &config.clients {
+        engine.clients = clients::load_clients(std::fs::File::open(filename)?)?;
+    }
+    if let Some(reservation) = &config.reservation {
+        engine.reservation = reservation.parse()?;
+    }
+    if let Some(as_of) = &config.as_of {
+        engine.as_of = Some(DateTime::parse_from_rfc3339(as_of)?);
+    }
+    if let Some(out_of_order) = &config.out_of_order {
+        engine.out_of_order = out_of_order.parse()?;
+    }
+    if let Some(days) = &config.authorization_days {
+        let lifetime = match days.parse::<i64>() {
+            Ok(days) if (0..=MAX_AUTHORIZATION_DAYS).contains(&days) => TimeDelta::days(days),
+            _ => return Err(format!("authorization days {} is out of range", days).into()),
+        };
+        engine.authorization_lifetime = Some(lifetime);
+    }
+    if let Some(filename) = &config.dispute_windows {
+        engine.dispute_windows = DisputeWindows::load(std::fs::File::open(filename)?)?;
+    }
+    if let Some(filename) = &config.rules {
+        engine.rules = RuleSet::load(std::fs::File::open(filename)?)?;
+    }
+    if let Some(filename) = &config.operators {
+        engine.operators = operators::load_operators(std::fs::File::open(filename)?)?;
+    }
+    if let Some(filename) = &config.heuristics {
+        engine.monitor = Monitor::load(std::fs::File::open(filename)?)?;
+    }
+    if config.check {
+        engine.check_invariants();
+    }
+    Ok(engine)
+}
+
+/// inspect replays the transactions of the config and returns every step
+/// that concerned `client`, with its balances after each of them.
+pub fn inspect(config: &CliConfig, client: u16) -> Result<Vec<Step>, Box<dyn Error>> {
+    let mut engine = load_engine(config)?;
+    replay(&mut engine, std::fs::File::open(&config.filename)?, client)
+}
+
+/// replay applies the transactions of `reader` and keeps the steps that
+/// concerned `client`: the ones it sent or received, and any other that
+/// changed its account.
+fn replay<R: io::Read>(
+    engine: &mut Engine,
+    reader: R,
+    client: u16,
+) -> Result<Vec<Step>, Box<dyn Error>> {
+    let mut steps = vec![];
+    // the ledger is not kept for inspection
+    each_transaction(engine, reader, false, |engine, txn| {
+        let before = engine.stats.get(&client).cloned();
+        let involved = txn.client == client || txn.destination == Some(client);
+        let (r#type, tx, currency) = (txn.r#type.clone(), txn.tx, txn.currency.clone());
+        let rejection = engine.apply(txn).err();
+
+        let after = engine.stats.get(&client);
+        if !involved && before.as_ref() == after {
+            return Ok(true);
+        }
+        let mut changed: Vec<Account> = after
+            .map(|stat| {
+                accounts(stat, &engine.clients)
+                    .filter(|account| {
+                        let previous = before
+                            .as_ref()
+                            .map(|before| before.balance(&account.currency));
+                        previous != Some(stat.balance(&account.currency))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        if changed.is_empty() {
+            changed = match after {
+                Some(stat) => accounts(stat, &engine.clients)
+                    .filter(|account| account.currency == currency)
+                    .collect(),
+                None => vec![],
+            };
+        }
+        steps.push(Step {
+            tx,
+            r#type,
+            rejection: rejection.map(|rejection| rejection.to_string()),
+            accounts: changed,
+        });
+        Ok(true)
+    })?;
+    Ok(steps)
+}
+
+/// write_inspection writes the steps of an inspection, one row for each
+/// balance they show.
+fn write_inspection<W: io::Write>(writer: W, steps: &[Step]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    let none = None;
+    for step in steps.iter() {
+        let row = |currency, balance: Option<&Account>| InspectRow {
+            tx: step.tx,
+            r#type: &step.r#type,
+            currency,
+            available: balance.map_or(0.0, |account| account.available),
+            held: balance.map_or(0.0, |account| account.held),
+            total: balance.map_or(0.0, |account| account.total),
+            locked: balance.is_some_and(|account| account.locked),
+            reason: &step.rejection,
+        };
+        if step.accounts.is_empty() {
+            wtr.serialize(row(&none, None))?;
+        }
+        for account in step.accounts.iter() {
+            wtr.serialize(row(&account.currency, Some(account)))?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// statements replays the transactions of the config and returns the
+/// statement of every account over the period from `from` until before `to`.
+pub fn statements(
+    config: &CliConfig,
+    from: Option<DateTime<FixedOffset>>,
+    to: Option<DateTime<FixedOffset>>,
+) -> Result<Vec<Statement>, Box<dyn Error>> {
+    if let (Some(from), Some(to)) = (from, to) {
+        if to <= from {
+            return Err("statement period ends before it starts".into());
+        }
+    }
+    let mut engine = load_engine(config)?;
+    state(
+        &mut engine,
+        std::fs::File::open(&config.filename)?,
+        from,
+        to,
+    )
+}
+
+/// state applies the transactions of `reader` and records what they did over
+/// the period. Rows are taken in file order: the period starts at the first
+/// row timestamped at `from` or later, and ends at the first one timestamped
+/// at `to` or later.
+fn state<R: io::Read>(
+    engine: &mut Engine,
+    reader: R,
+    from: Option<DateTime<FixedOffset>>,
+    to: Option<DateTime<FixedOffset>>,
+) -> Result<Vec<Statement>, Box<dyn Error>> {
+    let mut statements = Statements::default();
+    let mut started = from.is_none();
+    if started {
+        open_statements(engine, &mut statements);
+    }
+    engine.ledger.journal.clear();
+
+    let mut ended = false;
+    each_transaction(engine, reader, false, |engine, txn| {
+        if let Some(timestamp) = txn.timestamp {
+            if to.is_some_and(|to| timestamp >= to) {
+                ended = true;
+                return Ok(false);
+            }
+            if let Some(from) = from.filter(|from| !started && timestamp >= *from) {
+                engine.expire(from);
+                engine.ledger.journal.clear();
+                open_statements(engine, &mut statements);
+                started = true;
+            }
+        }
+        let (kind, client, timestamp) = (txn.r#type.clone(), txn.client, txn.timestamp);
+        if let Some(timestamp) = timestamp.filter(|_| engine.admissible(&txn).is_ok()) {
+            record_expiries(engine, &mut statements, timestamp, started);
+        }
+        let _ = engine.apply(txn);
+        record_postings(
+            engine,
+            &mut statements,
+            &kind,
+            timestamp,
+            Some(client),
+            started,
+        );
+        Ok(true)
+    })?;
+    if !started {
+        open_statements(engine, &mut statements);
+    } else if let Some(end) = to.filter(|_| ended).or(engine.as_of) {
+        record_expiries(engine, &mut statements, end, true);
+    }
+    Ok(statements.finish())
+}
+
+/// record_expiries runs out the disputes and authorizations of the engine at
+/// `now`, recording the disputes as `expire` and the authorizations as `void`.
+fn record_expiries(
+    engine: &mut Engine,
+    statements: &mut Statements,
+    now: DateTime<FixedOffset>,
+    started: bool,
+) {
+    engine.expire_disputes(now);
+    record_postings(engine, statements, "expire", Some(now), None, started);
+    engine.expire_authorizations(now);
+    record_postings(engine, statements, "void", Some(now), None, started);
+}
+
+/// record_postings records the postings of the engine made by a `kind`
+/// transaction of `payer`, or drops them before the period `started`.
+fn record_postings(
+    engine: &mut Engine,
+    statements: &mut Statements,
+    kind: &str,
+    timestamp: Option<DateTime<FixedOffset>>,
+    payer: Option<u16>,
+    started: bool,
+) {
+    for posting in engine.ledger.journal.drain(..) {
+        if started {
+            statements.record(kind, timestamp, payer, &posting);
+        }
+    }
 }
 
-pub fn run(config: CliConfig) -> Result<(), Box<dyn Error>> {
-    let mut stats = HashMap::new();
-    let mut records = vec![];
+/// open_statements starts the statement of every account the engine holds.
+fn open_statements(engine: &Engine, statements: &mut Statements) {
+    for stat in engine.stats.values() {
+        for (currency, balance) in stat.balances.iter() {
+            let opening = Totals {
+                available: balance.available,
+                held: balance.held,
+                total: balance.total,
+            };
+            statements.open(stat.client, currency, opening);
+        }
+    }
+}
 
+/// each_transaction reads the transactions of `reader` and hands them one by
+/// one to `step`, which applies them to `engine` and tells whether to go on.
+/// A row that cannot be read ends the replay with its error, or is skipped
+/// with `skip_unreadable`. What `step` leaves in the journal is dropped.
+fn each_transaction<R: io::Read>(
+    engine: &mut Engine,
+    reader: R,
+    skip_unreadable: bool,
+    mut step: impl FnMut(&mut Engine, Transaction) -> Result<bool, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
     let mut rdr = csv::ReaderBuilder::new()
         .trim(Trim::All)
-        // from_path buffers by default, so we can expect the stream of data
-        // rather than loading the entire data in the memory.
-        .from_path(config.filename)?;
-
+        .from_reader(reader);
     for transaction in rdr.deserialize() {
-        let txn: Transaction = transaction?;
-        process_txn(txn, &mut records, &mut stats);
+        let txn: Transaction = match transaction {
+            Ok(txn) => txn,
+            Err(_) if skip_unreadable => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let go_on = step(engine, txn)?;
+        engine.ledger.journal.clear();
+        if !go_on {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// check replays the transactions of `reader` with the invariants verified
+/// after every one of them, as `--check` does, and returns the accounts.
+/// Rows that cannot be read are skipped rather than ending the replay.
+pub fn check<R: io::Read>(config: &CliConfig, reader: R) -> Result<Vec<Account>, Box<dyn Error>> {
+    let mut engine = load_engine(config)?;
+    engine.check_invariants();
+    each_transaction(&mut engine, reader, true, |engine, txn| {
+        let _ = engine.apply_verified(txn)?;
+        Ok(true)
+    })?;
+    if let Some(as_of) = engine.as_of {
+        engine.expire(as_of);
+    }
+    engine.check_ledger()?;
+    Ok(engine
+        .stats
+        .values()
+        .flat_map(|stat| accounts(stat, &engine.clients))
+        .collect())
+}
+
+/// rejected applies the transactions of `reader` to an engine without any
+/// configuration and returns the ones it rejected, for the tests of the
+/// modules making up transactions.
+#[cfg(test)]
+pub(crate) fn rejected<R: io::Read>(reader: R) -> Result<Vec<(u32, String)>, Box<dyn Error>> {
+    let mut engine = Engine::default();
+    let mut rejected = vec![];
+    each_transaction(&mut engine, reader, false, |engine, txn| {
+        let tx = txn.tx;
+        if let Err(rejection) = engine.apply(txn) {
+            rejected.push((tx, rejection.to_string()));
+        }
+        Ok(true)
+    })?;
+    Ok(rejected)
+}
+
+/// run runs the command of `config` and writes what it outputs to `writer`,
+/// the files it is given to write aside. It returns false when a reconcile
+/// finds breaks.
+pub fn run<W: io::Write>(config: CliConfig, mut writer: W) -> Result<bool, Box<dyn Error>> {
+    match config.command {
+        Command::Inspect { client } => {
+            let steps = inspect(&config, client)?;
+            write_inspection(writer, &steps)?;
+            return Ok(true);
+        }
+        Command::Statement { client } => {
+            let parse = |time: &Option<String>| {
+                time.as_deref()
+                    .map(DateTime::parse_from_rfc3339)
+                    .transpose()
+            };
+            let (from, to) = (parse(&config.from)?, parse(&config.to)?);
+            let format: Format = config.format.as_deref().unwrap_or("csv").parse()?;
+            let mut statements = statements(&config, from, to)?;
+            statements.retain(|statement| client.is_none_or(|client| statement.client == client));
+            match format {
+                Format::Csv => statements::write_csv(writer, &statements)?,
+                Format::Text => statements::write_text(writer, &statements, from, to)?,
+            };
+            return Ok(true);
+        }
+        Command::Generate => {
+            generator::run(&config)?;
+            return Ok(true);
+        }
+        Command::Reconcile => return reconcile::run(&config, writer),
+        Command::Run => {}
+    }
+
+    let mut engine = load_engine(&config)?;
+    let mut journal = match config.journal {
+        Some(filename) => Some(csv::Writer::from_path(filename)?),
+        None => None,
+    };
+    write_journal(&mut journal, &mut engine.ledger)?;
+    let mut rejections = match config.rejections {
+        Some(filename) => Some(csv::Writer::from_path(filename)?),
+        None => None,
+    };
+
+    // the csv reader buffers, so we can expect the stream of data rather
+    // than loading the entire data in the memory.
+    let file = std::fs::File::open(&config.filename)?;
+    each_transaction(&mut engine, file, false, |engine, txn| {
+        let (r#type, client, tx) = (txn.r#type.clone(), txn.client, txn.tx);
+        if let Err(rejection) = engine.apply_verified(txn)? {
+            if let Some(wtr) = rejections.as_mut() {
+                wtr.serialize(RejectedTransaction {
+                    r#type,
+                    client,
+                    tx,
+                    reason: rejection.to_string(),
+                })?;
+            }
+        }
+        write_journal(&mut journal, &mut engine.ledger)?;
+        Ok(true)
+    })?;
+    if let Some(mut wtr) = rejections {
+        wtr.flush()?;
+    }
+    if let Some(as_of) = engine.as_of {
+        engine.expire(as_of);
+    }
+    write_journal(&mut journal, &mut engine.ledger)?;
+    if let Some(mut wtr) = journal {
+        wtr.flush()?;
     }
+    engine.check_ledger()?;
 
     let mut wtr = csv::Writer::from_writer(vec![]);
-    for (_, stat) in stats.iter() {
-        wtr.serialize(stat)?;
+    for (_, stat) in engine.stats.iter() {
+        for account in accounts(stat, &engine.clients) {
+            wtr.serialize(account)?;
+        }
+    }
+    writer.write_all(&wtr.into_inner()?)?;
+
+    if let Some(filename) = config.house_accounts {
+        write_house_accounts(std::fs::File::create(filename)?, &engine.house)?;
+    }
+    if let Some(filename) = config.flags {
+        let mut wtr = csv::Writer::from_path(filename)?;
+        for flag in engine.flags.iter() {
+            wtr.serialize(flag)?;
+        }
+        wtr.flush()?;
+    }
+    if let Some(filename) = config.audit {
+        write_audit(std::fs::File::create(filename)?, &engine.audit)?;
+    }
+    Ok(true)
+}
+
+/// write_journal writes the postings of the ledger made since the last call
+/// when a journal is kept, and forgets them either way.
+fn write_journal<W: io::Write>(
+    wtr: &mut Option<csv::Writer<W>>,
+    ledger: &mut Ledger,
+) -> Result<(), Box<dyn Error>> {
+    for posting in ledger.journal.drain(..) {
+        if let Some(wtr) = wtr.as_mut() {
+            for entry in posting.entries.iter() {
+                wtr.serialize(JournalRow {
+                    tx: posting.tx,
+                    account: entry.account.name(),
+                    client: entry.account.client(),
+                    currency: &entry.currency,
+                    amount: entry.amount,
+                })?;
+            }
+        }
     }
-    let data = String::from_utf8(wtr.into_inner()?)?;
-    print!("{}", data);
     Ok(())
 }
 
-fn process_txn(txn: Transaction, records: &mut Vec<Record>, stats: &mut HashMap<u16, Stats>) {
-    let pos = match records.binary_search_by(|record: &Record| record.txn.tx.cmp(&txn.tx)) {
-        Ok(pos) => pos,
-        Err(pos) => pos,
-    };
-    // no need to add the dispute, resolve, and chargeback transactions
-    if txn.r#type == "deposit" || txn.r#type == "withdrawal" {
-        records.insert(
-            pos,
-            Record {
-                // TODO: make it unclone
-                txn: txn.clone(),
-                has_dispute: false,
-            },
-        );
+/// write_audit writes who sent each admin transaction and why.
+fn write_audit<W: io::Write>(writer: W, audit: &[AdminAction]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for action in audit.iter() {
+        wtr.serialize(action)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// write_house_accounts writes every house account with its balance per
+/// currency.
+fn write_house_accounts<W: io::Write>(writer: W, house: &House) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    let accounts = [
+        ("exchange_spread", &house.exchange_spread),
+        ("fees", &house.fees),
+    ];
+    for (account, balances) in accounts {
+        for (currency, amount) in balances.iter() {
+            wtr.serialize(HouseAccount {
+                account,
+                currency,
+                amount: *amount,
+            })?;
+        }
     }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// accounts flattens the balances of a client into output rows.
+fn accounts<'a>(
+    stat: &'a Stats,
+    clients: &'a HashMap<u16, ClientConfig>,
+) -> impl Iterator<Item = Account> + 'a {
+    stat.balances.iter().map(|(currency, balance)| Account {
+        client: stat.client,
+        currency: currency.clone(),
+        available: balance.available,
+        held: balance.held,
+        total: balance.total,
+        locked: stat.locked,
+        flagged: stat.flagged,
+        closed: stat.closed,
+        // only the part of a negative balance the credit line covers, a
+        // disputed deposit that was spent can leave more
+        credit_used: if balance.available < 0.0 {
+            (-balance.available).min(credit_limit(clients, stat.client, currency))
+        } else {
+            0.0
+        },
+    })
+}
 
-    match txn.r#type.as_str() {
-        "deposit" => {
-            let entry = stats.entry(txn.client).or_insert(Stats {
-                client: txn.client,
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
-                locked: false,
-            });
-            if let Some(amount) = txn.amount {
-                entry.available = round(entry.available + amount);
-                entry.total = round(entry.total + amount);
+/// load_opening_balances seeds `stats` from a previous run's output, so runs
+/// can be chained without replaying the full transaction history.
+fn load_opening_balances<R: io::Read>(
+    reader: R,
+    engine: &mut Engine,
+) -> Result<(), Box<dyn Error>> {
+    let Engine {
+        stats,
+        house,
+        ledger,
+        ..
+    } = engine;
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(reader);
+
+    for account in rdr.deserialize() {
+        let account: Account = account?;
+        if let Some(code) = &account.currency {
+            if currency::minor_units(code).is_none() {
+                return Err(format!(
+                    "opening balance for client {} has unknown currency {}",
+                    account.client, code
+                )
+                .into());
             }
         }
-        "withdrawal" => {
-            if let Some(entry) = stats.get_mut(&txn.client) {
-                if let Some(amount) = txn.amount {
-                    // insufficient balance check
-                    if (entry.total - amount) < 0.0 {
-                        return;
-                    }
-                    entry.available = round(entry.available - amount);
-                    entry.total = round(entry.total - amount);
-                }
-            }
-        }
-        "dispute" => {
-            if let Some(entry) = stats.get_mut(&txn.client) {
-                match records.binary_search_by(|record| record.txn.tx.cmp(&txn.tx)) {
-                    Ok(pos) => {
-                        if let Some(prev_record) = records.get_mut(pos) {
-                            prev_record.has_dispute = true;
-                            match prev_record.txn.r#type.as_str() {
-                                "deposit" => {
-                                    entry.available =
-                                        round(entry.available - prev_record.txn.amount.unwrap());
-                                    entry.held =
-                                        round(entry.held + prev_record.txn.amount.unwrap());
-                                }
-                                "withdrawal" => {
-                                    entry.available =
-                                        round(entry.available + prev_record.txn.amount.unwrap());
-                                    entry.held =
-                                        round(entry.held - prev_record.txn.amount.unwrap());
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                    Err(_pos) => {}
-                }
-            }
-        }
-        "resolve" => {
-            if let Some(entry) = stats.get_mut(&txn.client) {
-                match records.binary_search_by(|record| record.txn.tx.cmp(&txn.tx)) {
-                    Ok(pos) => {
-                        if let Some(prev_record) = records.get_mut(pos) {
-                            if prev_record.has_dispute {
-                                match prev_record.txn.r#type.as_str() {
-                                    "deposit" => {
-                                        entry.available = round(
-                                            entry.available + prev_record.txn.amount.unwrap(),
-                                        );
-                                        entry.held =
-                                            round(entry.held - prev_record.txn.amount.unwrap());
-                                    }
-                                    "withdrawal" => {
-                                        entry.available = round(
-                                            entry.available - prev_record.txn.amount.unwrap(),
-                                        );
-                                        entry.held =
-                                            round(entry.held + prev_record.txn.amount.unwrap());
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-                    }
-                    Err(_pos) => {}
-                }
-            }
-        }
-        "chargeback" => {
-            if let Some(entry) = stats.get_mut(&txn.client) {
-                match records.binary_search_by(|record| record.txn.tx.cmp(&txn.tx)) {
-                    Ok(pos) => {
-                        if let Some(prev_record) = records.get_mut(pos) {
-                            if prev_record.has_dispute {
-                                match prev_record.txn.r#type.as_str() {
-                                    "deposit" => {
-                                        entry.total =
-                                            round(entry.total - prev_record.txn.amount.unwrap());
-                                        entry.held =
-                                            round(entry.held - prev_record.txn.amount.unwrap());
-                                    }
-                                    "withdrawal" => {
-                                        entry.total =
-                                            round(entry.total + prev_record.txn.amount.unwrap());
-                                        entry.held =
-                                            round(entry.held + prev_record.txn.amount.unwrap());
-                                    }
-                                    _ => {}
-                                }
-                                entry.locked = true;
-                            }
-                        }
+        let currency = &account.currency;
+        let amounts = [account.available, account.held, account.total];
+        if !amounts.into_iter().all(valid_amount) {
+            return Err(format!(
+                "opening balance for client {} has an invalid amount",
+                account.client
+            )
+            .into());
+        }
+        let delta = account.available + account.held - account.total;
+        let scale = account.available.abs() + account.held.abs() + account.total.abs();
+        if !currency::negligible(delta, scale, currency) {
+            return Err(format!(
+                "opening balance for client {} is inconsistent: available {} + held {} != total {}",
+                account.client, account.available, account.held, account.total
+            )
+            .into());
+        }
+
+        let client = account.client;
+        if stats
+            .get(&client)
+            .is_some_and(|entry| entry.balances.contains_key(currency))
+        {
+            return Err(format!("duplicate opening balance for client {}", client).into());
+        }
+        let available = round(account.available, currency);
+        let held = round(account.held, currency);
+        // the funds come from outside, before the history of this run
+        let entries = vec![
+            Entry::new(Available(client), currency, available),
+            Entry::new(Held(client), currency, held),
+            Entry::new(External, currency, -(available + held)),
+        ];
+        post(ledger, stats, house, None, entries).map_err(|_| {
+            format!(
+                "opening balance for client {} cannot be kept exactly",
+                client
+            )
+        })?;
+
+        let entry = stats.entry(client).or_insert_with(|| Stats::new(client));
+        // locking applies to the whole client, not a single currency
+        entry.locked = entry.locked || account.locked;
+        entry.flagged = entry.flagged || account.flagged;
+        entry.closed = entry.closed || account.closed;
+    }
+    Ok(())
+}
+
+impl Engine {
+    /// admit checks the timestamp of a transaction and expires the disputes
+    /// and authorizations that ran out by then. Admitting the same
+    /// transaction again changes nothing.
+    fn admit(&mut self, txn: &Transaction) -> Result<(), Rejection> {
+        self.admissible(txn)?;
+        if let Some(timestamp) = txn.timestamp {
+            self.expire(timestamp);
+        }
+        Ok(())
+    }
+
+    /// admissible checks the timestamp of a transaction against the as-of
+    /// time and the latest one of its client.
+    fn admissible(&self, txn: &Transaction) -> Result<(), Rejection> {
+        if let Some(timestamp) = txn.timestamp {
+            if self.as_of.is_some_and(|as_of| timestamp > as_of) {
+                return Err(Rejection::AfterAsOf);
+            }
+            let last = self.last_seen.get(&txn.client);
+            if self.out_of_order == OutOfOrder::Reject && last.is_some_and(|last| timestamp < *last)
+            {
+                return Err(Rejection::OutOfOrder);
+            }
+        }
+        Ok(())
+    }
+
+    /// apply admits a transaction and runs the risk rules on it before it is
+    /// applied. The rules and heuristics only learn about transactions that
+    /// were applied.
+    fn apply(&mut self, txn: Transaction) -> Result<(), Rejection> {
+        let kind = txn.r#type.clone();
+        let currency = txn.currency.clone();
+        let (client, tx, amount, timestamp) = (txn.client, txn.tx, txn.amount, txn.timestamp);
+        self.admit(&txn)?;
+        // days of the daily rules are UTC days
+        let date = timestamp.map(|timestamp| timestamp.naive_utc().date());
+        self.rules
+            .check(&kind, client, currency.as_deref(), amount, date)
+            .map_err(Rejection::Rule)?;
+        self.process_txn(txn)?;
+        self.rules
+            .record(&kind, client, currency.as_deref(), amount, date);
+        if let Some(timestamp) = timestamp {
+            let last = self.last_seen.entry(client).or_insert(timestamp);
+            *last = (*last).max(timestamp);
+        }
+
+        for flag in self
+            .monitor
+            .observe(&kind, client, tx, currency.as_deref(), amount)
+        {
+            if let Some(entry) = self.stats.get_mut(&client) {
+                entry.flagged = true;
+                entry.locked = entry.locked || flag.action == Action::Lock;
+            }
+            self.flags.push(flag);
+        }
+        Ok(())
+    }
+
+    /// apply_verified applies `txn` and, when the invariants are checked,
+    /// verifies them on the accounts it touched. It only fails on a broken
+    /// invariant, the rejection of the transaction is its result.
+    fn apply_verified(&mut self, txn: Transaction) -> Result<Result<(), Rejection>, String> {
+        let watch = self.watch(&txn);
+        let result = self.apply(txn);
+        if let Some(watch) = watch {
+            self.verify(watch)?;
+        }
+        Ok(result)
+    }
+
+    /// check_ledger verifies that the ledger sums to zero and that every
+    /// client balance is what the ledger holds for it.
+    fn check_ledger(&self) -> Result<(), String> {
+        self.ledger.check()?;
+        for stat in self.stats.values() {
+            for (currency, balance) in stat.balances.iter() {
+                let available = self.ledger.balance(Available(stat.client), currency);
+                let held = self.ledger.balance(Held(stat.client), currency);
+                if balance.available != available
+                    || balance.held != held
+                    || !currency::negligible(
+                        balance.total - available - held,
+                        balance.total.abs() + available.abs() + held.abs(),
+                        currency,
+                    )
+                {
+                    return Err(format!(
+                        "balance of client {} is {:?} but the ledger holds available {} and held {}",
+                        stat.client, balance, available, held
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// check_invariants turns on the checks of `verify`, starting from the
+    /// held funds the accounts have now.
+    fn check_invariants(&mut self) {
+        let mut invariants = Invariants::default();
+        for stat in self.stats.values() {
+            for (currency, balance) in stat.balances.iter() {
+                invariants
+                    .held
+                    .insert((stat.client, currency.clone()), balance.held);
+            }
+        }
+        self.invariants = Some(invariants);
+    }
+
+    /// watch takes what `verify` needs before a transaction applies, nothing
+    /// unless the invariants are checked.
+    fn watch(&self, txn: &Transaction) -> Option<Watch> {
+        self.invariants.as_ref()?;
+        Some(Watch {
+            tx: txn.tx,
+            kind: txn.r#type.clone(),
+            client: txn.client,
+            before: self.stats.get(&txn.client).cloned(),
+        })
+    }
+
+    /// verify checks every account the transaction of `watch` touched:
+    ///
+    /// - `total` is `available` plus `held`, and both are what the ledger holds.
+    /// - `held` is what open disputes and authorizations hold, so it only goes
+    ///   below zero while a debit is disputed.
+    /// - a locked account stays locked until it is unlocked.
+    /// - a closed account never changes.
+    ///
+    /// It fails with the state of the client before and after the transaction.
+    fn verify(&mut self, watch: Watch) -> Result<(), String> {
+        let Engine {
+            records,
+            stats,
+            ledger,
+            invariants,
+            ..
+        } = self;
+        let invariants = match invariants.as_mut() {
+            Some(invariants) => invariants,
+            None => return Ok(()),
+        };
+
+        // the recorded transactions that changed are the one sent and the
+        // ones disputes ran out for
+        let mut txs: BTreeSet<u32> = ledger.journal.iter().filter_map(|p| p.tx).collect();
+        txs.insert(watch.tx);
+        for tx in txs {
+            for (client, currency, amount) in invariants.holds.remove(&tx).unwrap_or_default() {
+                *invariants.held.entry((client, currency)).or_default() -= amount;
+            }
+            let start = records.partition_point(|record| record.txn.tx < tx);
+            let mut holds = vec![];
+            for record in records[start..]
+                .iter()
+                .take_while(|record| record.txn.tx == tx)
+            {
+                let currency = &record.txn.currency;
+                if record.hold != 0.0 {
+                    holds.push((record.txn.client, currency.clone(), record.hold));
+                }
+                for (client, amount) in credits(record, record.disputed) {
+                    if amount != 0.0 {
+                        holds.push((client, currency.clone(), amount));
                     }
-                    Err(_pos) => {}
                 }
             }
+            for (client, currency, amount) in holds.iter() {
+                *invariants
+                    .held
+                    .entry((*client, currency.clone()))
+                    .or_default() += amount;
+            }
+            if !holds.is_empty() {
+                invariants.holds.insert(tx, holds);
+            }
+        }
+
+        let mut clients: BTreeSet<u16> = ledger
+            .journal
+            .iter()
+            .flat_map(|posting| posting.entries.iter())
+            .filter_map(|entry| entry.account.client())
+            .collect();
+        clients.insert(watch.client);
+        for client in clients {
+            let after = match stats.get(&client) {
+                Some(after) => after,
+                None => continue,
+            };
+            let before = match client == watch.client {
+                true => watch.before.clone(),
+                false => None,
+            };
+            let posted = ledger
+                .journal
+                .iter()
+                .flat_map(|posting| posting.entries.iter())
+                .any(|entry| entry.account.client() == Some(client));
+
+            let mut broken = vec![];
+            for (currency, balance) in after.balances.iter() {
+                let code = currency.as_deref().unwrap_or("no currency");
+                let scale = balance.total.abs() + balance.available.abs() + balance.held.abs();
+                if !currency::negligible(
+                    balance.total - balance.available - balance.held,
+                    scale,
+                    currency,
+                ) {
+                    broken.push(format!("total in {} is not available plus held", code));
+                }
+                let available = ledger.balance(Available(client), currency);
+                let held = ledger.balance(Held(client), currency);
+                if balance.available != available || balance.held != held {
+                    broken.push(format!(
+                        "balance in {} is not the ledger's available {} and held {}",
+                        code, available, held
+                    ));
+                }
+                let expected = invariants
+                    .held
+                    .get(&(client, currency.clone()))
+                    .copied()
+                    .unwrap_or(0.0);
+                if !currency::negligible(balance.held - expected, scale, currency) {
+                    broken.push(format!(
+                        "held in {} is not the {} open disputes and authorizations hold",
+                        code,
+                        round(expected, currency)
+                    ));
+                }
+            }
+            if let Some(before) = before.as_ref() {
+                if before.locked && !after.locked && watch.kind != "unlock" {
+                    broken.push(format!("a {} unlocked the account", watch.kind));
+                }
+                if before.closed && before != after {
+                    broken.push("the account is closed but changed".to_string());
+                }
+            }
+            if after.closed && posted {
+                broken.push("the account is closed but was posted to".to_string());
+            }
+
+            if !broken.is_empty() {
+                return Err(format!(
+                    "invariants broken by tx {} ({}) for client {}: {}\n{}",
+                    watch.tx,
+                    watch.kind,
+                    client,
+                    broken.join(", "),
+                    diff(before.as_ref(), after)
+                ));
+            }
         }
-        _ => {}
+        Ok(())
     }
-}
 
-/// round is used for four digit precision
-fn round(amount: f32) -> f32 {
-    (amount * 10000_f32).round() / 10000_f32
-}
+    /// expire resolves the disputes and voids the authorizations that ran out
+    /// by `now`.
+    fn expire(&mut self, now: DateTime<FixedOffset>) {
+        self.expire_disputes(now);
+        self.expire_authorizations(now);
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::{collections::HashMap, vec};
+    /// expire_authorizations voids every authorization that still held funds
+    /// at its deadline, if that is not after `now`.
+    fn expire_authorizations(&mut self, now: DateTime<FixedOffset>) {
+        while let Some(&(deadline, tx)) = self.open_authorizations.first() {
+            if deadline > now {
+                break;
+            }
+            self.open_authorizations.pop_first();
+            let pos = match self
+                .records
+                .binary_search_by(|record| record.txn.tx.cmp(&tx))
+            {
+                Ok(pos) => pos,
+                Err(_) => continue,
+            };
+            let record = &mut self.records[pos];
+            // captured or voided before
+            if record.hold == 0.0 {
+                continue;
+            }
+            let entries = void_entries(record);
+            // voiding moves the held amount back, it always balances
+            if post(
+                &mut self.ledger,
+                &mut self.stats,
+                &mut self.house,
+                Some(tx),
+                entries,
+            )
+            .is_ok()
+            {
+                record.hold = 0.0;
+            }
+        }
+    }
 
-    use crate::app::process_txn;
+    /// expire_disputes resolves every dispute that was still open at its
+    /// deadline, if that is not after `now`.
+    fn expire_disputes(&mut self, now: DateTime<FixedOffset>) {
+        while let Some(&(deadline, tx)) = self.open_disputes.first() {
+            if deadline > now {
+                break;
+            }
+            self.open_disputes.pop_first();
+            let pos = match self
+                .records
+                .binary_search_by(|record| record.txn.tx.cmp(&tx))
+            {
+                Ok(pos) => pos,
+                Err(_) => continue,
+            };
+            let record = &mut self.records[pos];
+            let resolve_after = self.dispute_windows.resolve_after(&record.txn.r#type);
+            // a dispute that was closed and opened again has a later deadline
+            match (record.disputed_at, resolve_after) {
+                (Some(opened), Some(after)) if opened + after == deadline => {
+                    let amount = record.disputed;
+                    let entries = release_entries(record, amount);
+                    // releasing moves the same amounts back, it always balances
+                    if post(
+                        &mut self.ledger,
+                        &mut self.stats,
+                        &mut self.house,
+                        Some(tx),
+                        entries,
+                    )
+                    .is_ok()
+                    {
+                        release_dispute(record, amount);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn process_txn(&mut self, txn: Transaction) -> Result<(), Rejection> {
+        let Engine {
+            records,
+            stats,
+            house,
+            rates,
+            rates_date,
+            exchange_rounding,
+            fees,
+            clients,
+            reservation,
+            operators,
+            dispute_windows,
+            open_disputes,
+            authorization_lifetime,
+            open_authorizations,
+            ledger,
+            audit,
+            ..
+        } = self;
+        if let Some(code) = &txn.currency {
+            if currency::minor_units(code).is_none() {
+                return Err(Rejection::UnknownCurrency);
+            }
+        }
+        if txn.amount.is_some_and(|amount| !valid_amount(amount)) {
+            return Err(Rejection::InvalidAmount);
+        }
+        if stats.get(&txn.client).is_some_and(|entry| entry.closed) {
+            return Err(Rejection::AccountClosed);
+        }
+
+        // deposits and withdrawals are only recorded once applied, so that a
+        // rejected one cannot be disputed
+        let pos = match records.binary_search_by(|record: &Record| record.txn.tx.cmp(&txn.tx)) {
+            Ok(pos) => pos,
+            Err(pos) => pos,
+        };
+
+        let currency = &txn.currency;
+        // the fee is part of the transaction, it is only charged together
+        // with it and covered by the same funds check
+        let tier = clients
+            .get(&txn.client)
+            .and_then(|config| config.tier.as_deref());
+        let fee = match txn.amount {
+            Some(amount) if amount > 0.0 => round(
+                fees.fee(&txn.r#type, tier, currency.as_deref(), amount),
+                currency,
+            ),
+            _ => 0.0,
+        };
+        let limit = credit_limit(clients, txn.client, currency);
+        // what the client can spend in the currency of the transaction
+        let funds = |entry: &Stats| spendable(entry.balance(currency), limit, *reservation);
+        // the account a debit is taken from must exist and be open
+        let payer = |entry: Option<&Stats>| match entry {
+            Some(entry) if entry.locked => Err(Rejection::AccountLocked),
+            Some(entry) => Ok(funds(entry)),
+            None => Err(Rejection::UnknownClient),
+        };
+
+        match txn.r#type.as_str() {
+            "deposit" => {
+                // a locked account takes no new funds until it is unlocked
+                if stats.get(&txn.client).is_some_and(|entry| entry.locked) {
+                    return Err(Rejection::AccountLocked);
+                }
+                // a negative deposit would be a withdrawal without its rules and fee
+                if txn.amount.is_some_and(|amount| amount <= 0.0) {
+                    return Err(Rejection::InvalidAmount);
+                }
+                // only a fee larger than the deposit takes funds, a deposit
+                // otherwise never lowers the balance whatever it is
+                if let Some(amount) = txn.amount.filter(|amount| fee > *amount) {
+                    let available = stats.get(&txn.client).map_or(limit, funds);
+                    if available + amount - fee < 0.0 {
+                        return Err(Rejection::InsufficientFunds);
+                    }
+                }
+                if let Some(amount) = txn.amount {
+                    let mut entries = vec![
+                        Entry::new(Available(txn.client), currency, amount),
+                        Entry::new(External, currency, -amount),
+                    ];
+                    entries.extend(fee_entries(txn.client, currency, fee));
+                    post(ledger, stats, house, Some(txn.tx), entries)?;
+                }
+                stats
+                    .entry(txn.client)
+                    .or_insert_with(|| Stats::new(txn.client));
+                // TODO: make it unclone
+                let mut record = Record::new(txn.clone());
+                record.fee = fee;
+                records.insert(pos, record);
+            }
+            "withdrawal" => {
+                let available = payer(stats.get(&txn.client))?;
+                let amount = match txn.amount {
+                    Some(amount) if amount > 0.0 => amount,
+                    _ => return Err(Rejection::InvalidAmount),
+                };
+                // insufficient balance check
+                if (available - amount - fee) < 0.0 {
+                    return Err(Rejection::InsufficientFunds);
+                }
+                let mut entries = vec![
+                    Entry::new(Available(txn.client), currency, -amount),
+                    Entry::new(External, currency, amount),
+                ];
+                entries.extend(fee_entries(txn.client, currency, fee));
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+                records.insert(pos, Record::new(txn.clone()));
+            }
+            "transfer" => {
+                let (amount, destination) = match (txn.amount, txn.destination) {
+                    (Some(amount), Some(destination)) if amount > 0.0 => (amount, destination),
+                    (_, Some(_)) => return Err(Rejection::InvalidAmount),
+                    _ => return Err(Rejection::InvalidDestination),
+                };
+                if destination == txn.client {
+                    return Err(Rejection::InvalidDestination);
+                }
+                // both sides are checked before either is touched, so a transfer
+                // is applied entirely or not at all
+                if payer(stats.get(&txn.client))? - amount - fee < 0.0 {
+                    return Err(Rejection::InsufficientFunds);
+                }
+                match stats.get(&destination) {
+                    Some(entry) if entry.closed => return Err(Rejection::AccountClosed),
+                    Some(entry) if entry.locked => return Err(Rejection::AccountLocked),
+                    _ => {}
+                }
+
+                let mut entries = vec![
+                    Entry::new(Available(txn.client), currency, -amount),
+                    Entry::new(Available(destination), currency, amount),
+                ];
+                entries.extend(fee_entries(txn.client, currency, fee));
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+
+                records.insert(pos, Record::new(txn.clone()));
+            }
+            "refund" => {
+                let (amount, reference) = match (txn.amount, txn.reference) {
+                    (Some(amount), Some(reference)) if amount > 0.0 => {
+                        (round(amount, currency), reference)
+                    }
+                    (_, Some(_)) => return Err(Rejection::InvalidAmount),
+                    _ => return Err(Rejection::UnknownTransaction),
+                };
+                let original = match find_record(records, reference, txn.client) {
+                    Some(original)
+                        if original.txn.r#type == "deposit"
+                            && original.txn.currency == *currency =>
+                    {
+                        original
+                    }
+                    _ => return Err(Rejection::UnknownTransaction),
+                };
+                // disputed or charged back funds cannot be refunded as well
+                if amount > disputable(original) {
+                    return Err(Rejection::InvalidAmount);
+                }
+                if payer(stats.get(&txn.client))? - amount < 0.0 {
+                    return Err(Rejection::InsufficientFunds);
+                }
+                let entries = vec![
+                    Entry::new(Available(txn.client), currency, -amount),
+                    Entry::new(External, currency, amount),
+                ];
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+                original.refunded = round(original.refunded + amount, currency);
+                records.insert(pos, Record::new(txn.clone()));
+            }
+            "exchange" => {
+                let (from, to) = match (currency, &txn.to_currency) {
+                    (Some(from), Some(to)) if from != to => (from, to),
+                    _ => return Err(Rejection::NoRate),
+                };
+                let amount = match txn.amount {
+                    Some(amount) if amount > 0.0 => round(amount, currency),
+                    _ => return Err(Rejection::InvalidAmount),
+                };
+                // without a rates date a transaction gets the rate of its own day
+                let date =
+                    rates_date.or(txn.timestamp.map(|timestamp| timestamp.naive_utc().date()));
+                let rate = rates.lookup(from, to, date).ok_or(Rejection::NoRate)?;
+                let conversion = exchange::convert(amount, rate, *exchange_rounding);
+                let to_currency = &txn.to_currency;
+                if payer(stats.get(&txn.client))? - amount - fee < 0.0 {
+                    return Err(Rejection::InsufficientFunds);
+                }
+                // the exchange desk takes the amount in one currency and pays
+                // out its value in the other, split with the house
+                let mut entries = vec![
+                    Entry::new(Available(txn.client), currency, -amount),
+                    Entry::new(Exchange, currency, amount),
+                    Entry::new(
+                        Exchange,
+                        to_currency,
+                        -(conversion.credited + conversion.spread),
+                    ),
+                    Entry::new(Available(txn.client), to_currency, conversion.credited),
+                    Entry::new(ExchangeSpread, to_currency, conversion.spread),
+                ];
+                entries.extend(fee_entries(txn.client, currency, fee));
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+            }
+            "authorize" => {
+                let amount = match txn.amount {
+                    Some(amount) if amount > 0.0 => amount,
+                    _ => return Err(Rejection::InvalidAmount),
+                };
+                // the funds never leave the account until the capture, they
+                // are only moved out of reach of other spending
+                if payer(stats.get(&txn.client))? - amount < 0.0 {
+                    return Err(Rejection::InsufficientFunds);
+                }
+                let entries = vec![
+                    Entry::new(Available(txn.client), currency, -amount),
+                    Entry::new(Held(txn.client), currency, amount),
+                ];
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+                records.insert(pos, Record::new(txn.clone()));
+                // only authorizations with a timestamp run out
+                if let (Some(lifetime), Some(timestamp)) = (authorization_lifetime, txn.timestamp) {
+                    open_authorizations.insert((timestamp + *lifetime, txn.tx));
+                }
+            }
+            "capture" => {
+                let prev_record = find_authorization(records, txn.tx, txn.client)
+                    .ok_or(Rejection::UnknownTransaction)?;
+                let currency = &prev_record.txn.currency;
+                let hold = prev_record.hold;
+                // a capture without an amount takes the whole authorization
+                let amount = round(txn.amount.unwrap_or(hold), currency);
+                if amount <= 0.0 || amount > hold {
+                    return Err(Rejection::InvalidAmount);
+                }
+                // whatever is not captured goes back to the client
+                let entries = vec![
+                    Entry::new(Held(txn.client), currency, -hold),
+                    Entry::new(Available(txn.client), currency, hold - amount),
+                    Entry::new(External, currency, amount),
+                ];
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+                prev_record.hold = 0.0;
+                prev_record.captured = amount;
+            }
+            "void" => {
+                let prev_record = find_authorization(records, txn.tx, txn.client)
+                    .ok_or(Rejection::UnknownTransaction)?;
+                let entries = void_entries(prev_record);
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+                prev_record.hold = 0.0;
+            }
+            "dispute" => {
+                let prev_record = find_record(records, txn.tx, txn.client)
+                    .ok_or(Rejection::UnknownTransaction)?;
+                let currency = &prev_record.txn.currency;
+                let kind = prev_record.txn.r#type.as_str();
+                // a transaction can only be disputed for so long after it happened
+                if let (Some(max_age), Some(happened), Some(timestamp)) = (
+                    dispute_windows.max_age(kind),
+                    prev_record.txn.timestamp,
+                    txn.timestamp,
+                ) {
+                    if timestamp - happened > max_age {
+                        return Err(Rejection::DisputeExpired);
+                    }
+                }
+                // without an amount the rest of the transaction is disputed
+                let remaining = disputable(prev_record);
+                let amount = round(txn.amount.unwrap_or(remaining), currency);
+                if amount <= 0.0 || amount > remaining {
+                    return Err(Rejection::InvalidAmount);
+                }
+                if touches_closed(stats, prev_record, amount) {
+                    return Err(Rejection::AccountClosed);
+                }
+                let mut entries = vec![];
+                for (client, amount) in credits(prev_record, amount) {
+                    entries.push(Entry::new(Available(client), currency, -amount));
+                    entries.push(Entry::new(Held(client), currency, amount));
+                }
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+                // disputing more of a transaction keeps the deadline of its
+                // open dispute
+                if prev_record.disputed == 0.0 {
+                    prev_record.disputed_at = txn.timestamp;
+                    if let (Some(timestamp), Some(after)) =
+                        (txn.timestamp, dispute_windows.resolve_after(kind))
+                    {
+                        open_disputes.insert((timestamp + after, prev_record.txn.tx));
+                    }
+                }
+                prev_record.disputed = round(prev_record.disputed + amount, currency);
+            }
+            "resolve" => {
+                let prev_record = find_record(records, txn.tx, txn.client)
+                    .ok_or(Rejection::UnknownTransaction)?;
+                let currency = &prev_record.txn.currency;
+                if prev_record.disputed <= 0.0 {
+                    return Err(Rejection::NotDisputed);
+                }
+                let amount = round(txn.amount.unwrap_or(prev_record.disputed), currency);
+                if amount <= 0.0 || amount > prev_record.disputed {
+                    return Err(Rejection::InvalidAmount);
+                }
+                if touches_closed(stats, prev_record, amount) {
+                    return Err(Rejection::AccountClosed);
+                }
+                let entries = release_entries(prev_record, amount);
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+                release_dispute(prev_record, amount);
+            }
+            "chargeback" => {
+                let prev_record = find_record(records, txn.tx, txn.client)
+                    .ok_or(Rejection::UnknownTransaction)?;
+                let currency = &prev_record.txn.currency;
+                if prev_record.disputed <= 0.0 {
+                    return Err(Rejection::NotDisputed);
+                }
+                let amount = round(txn.amount.unwrap_or(prev_record.disputed), currency);
+                if amount <= 0.0 || amount > prev_record.disputed {
+                    return Err(Rejection::InvalidAmount);
+                }
+                if touches_closed(stats, prev_record, amount) {
+                    return Err(Rejection::AccountClosed);
+                }
+                let charged_back = round(prev_record.charged_back + amount, currency);
+                // the held funds go back to where they came from
+                let mut entries = vec![];
+                for (client, amount) in credits(prev_record, amount) {
+                    entries.push(Entry::new(Held(client), currency, -amount));
+                    entries.push(Entry::new(External, currency, amount));
+                }
+                let (fee_refunded, refund) = refund_fee(prev_record, charged_back);
+                entries.extend(refund);
+                post(ledger, stats, house, Some(txn.tx), entries)?;
+                prev_record.disputed = round(prev_record.disputed - amount, currency);
+                prev_record.charged_back = charged_back;
+                prev_record.fee_refunded = fee_refunded;
+                if prev_record.disputed == 0.0 {
+                    prev_record.disputed_at = None;
+                }
+                if let Some(entry) = stats.get_mut(&txn.client) {
+                    entry.locked = true;
+                }
+            }
+            "unlock" | "freeze" | "close" => {
+                let operator = match &txn.operator {
+                    Some(operator) if operators.contains(operator) => operator,
+                    _ => return Err(Rejection::Unauthorized),
+                };
+                let reason = match &txn.reason {
+                    Some(reason) if !reason.is_empty() => reason,
+                    _ => return Err(Rejection::MissingReason),
+                };
+                let entry = stats.get_mut(&txn.client).ok_or(Rejection::UnknownClient)?;
+                match txn.r#type.as_str() {
+                    "unlock" => entry.locked = false,
+                    "freeze" => entry.locked = true,
+                    _ => {
+                        // held funds and pending authorizations count as well
+                        let empty = entry.balances.values().all(|balance| {
+                            balance.available == 0.0 && balance.held == 0.0 && balance.total == 0.0
+                        });
+                        if !empty {
+                            return Err(Rejection::BalanceNotZero);
+                        }
+                        entry.closed = true;
+                    }
+                }
+                audit.push(AdminAction {
+                    tx: txn.tx,
+                    client: txn.client,
+                    action: txn.r#type.clone(),
+                    operator: operator.clone(),
+                    reason: reason.clone(),
+                });
+            }
+            _ => return Err(Rejection::UnknownType),
+        }
+        Ok(())
+    }
+}
+
+/// credit_limit returns how far `client` may overdraw its balance in
+/// `currency`.
+fn credit_limit(
+    clients: &HashMap<u16, ClientConfig>,
+    client: u16,
+    currency: &Option<String>,
+) -> f32 {
+    match clients.get(&client) {
+        Some(config) if config.currency == *currency => config.credit_limit.unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+/// spendable returns what a client can still take out of `balance`, with
+/// held funds treated as `reservation` says.
+fn spendable(balance: Balance, limit: f32, reservation: Reservation) -> f32 {
+    let funds = match reservation {
+        Reservation::Available => balance.available,
+        // a disputed debit leaves held below zero and pays its amount back
+        // into available until the dispute is settled
+        Reservation::Strict => balance.available + balance.held.min(0.0),
+        Reservation::Total => balance.total,
+    };
+    funds + limit
+}
+
+/// fee_entries move a transaction fee from the client's balance to the house.
+fn fee_entries(client: u16, currency: &Option<String>, fee: f32) -> Vec<Entry> {
+    if fee <= 0.0 {
+        return vec![];
+    }
+    vec![
+        Entry::new(Available(client), currency, -fee),
+        Entry::new(Fees, currency, fee),
+    ]
+}
+
+/// refund_fee gives the fee of a deposit back to the client for the part of
+/// it that is charged back once `charged_back` is. It returns the fee
+/// refunded by then, with the entries refunding what was not yet.
+fn refund_fee(record: &Record, charged_back: f32) -> (f32, Vec<Entry>) {
+    let currency = &record.txn.currency;
+    let amount = record.txn.amount.unwrap_or(0.0);
+    if record.txn.r#type != "deposit" || record.fee <= 0.0 || amount <= 0.0 {
+        return (record.fee_refunded, vec![]);
+    }
+    // working from the charged back total rather than each chargeback keeps
+    // partial chargebacks from leaving rounding leftovers behind
+    let due = round(record.fee * charged_back / amount, currency);
+    let refund = round(due - record.fee_refunded, currency);
+    if refund <= 0.0 {
+        return (record.fee_refunded, vec![]);
+    }
+    let entries = vec![
+        Entry::new(Available(record.txn.client), currency, refund),
+        Entry::new(Fees, currency, -refund),
+    ];
+    (due, entries)
+}
+
+/// post records the entries of transaction `tx` in the ledger and applies
+/// them to the client balances and house accounts that follow it. Nothing
+/// changes when the ledger refuses them.
+fn post(
+    ledger: &mut Ledger,
+    stats: &mut HashMap<u16, Stats>,
+    house: &mut House,
+    tx: Option<u32>,
+    entries: Vec<Entry>,
+) -> Result<(), Rejection> {
+    let posting = ledger
+        .post(tx, entries)
+        .map_err(|_| Rejection::Unbalanced)?;
+    // balances are read back from the ledger rather than summed again in
+    // f32, for them not to drift from it
+    let accounts: Vec<_> = posting
+        .entries
+        .iter()
+        .map(|entry| (entry.account, entry.currency.clone()))
+        .collect();
+    for (account, currency) in accounts {
+        let currency = &currency;
+        match account {
+            Available(client) | Held(client) => {
+                let balance = stats
+                    .entry(client)
+                    .or_insert_with(|| Stats::new(client))
+                    .balances
+                    .entry(currency.clone())
+                    .or_default();
+                balance.available = ledger.balance(Available(client), currency);
+                balance.held = ledger.balance(Held(client), currency);
+                balance.total = round(balance.available + balance.held, currency);
+            }
+            Fees => {
+                house
+                    .fees
+                    .insert(currency.clone(), ledger.balance(Fees, currency));
+            }
+            ExchangeSpread => {
+                house
+                    .exchange_spread
+                    .insert(currency.clone(), ledger.balance(ExchangeSpread, currency));
+            }
+            Exchange | External => {}
+        }
+    }
+    Ok(())
+}
+
+/// release_entries move `amount` of the open dispute of `record` back from
+/// held to available for every client it touched.
+fn release_entries(record: &Record, amount: f32) -> Vec<Entry> {
+    let currency = &record.txn.currency;
+    let mut entries = vec![];
+    for (client, amount) in credits(record, amount) {
+        entries.push(Entry::new(Available(client), currency, amount));
+        entries.push(Entry::new(Held(client), currency, -amount));
+    }
+    entries
+}
+
+/// void_entries returns the entries releasing what is left of the
+/// authorization of `record`.
+fn void_entries(record: &Record) -> Vec<Entry> {
+    let (client, currency) = (record.txn.client, &record.txn.currency);
+    vec![
+        Entry::new(Held(client), currency, -record.hold),
+        Entry::new(Available(client), currency, record.hold),
+    ]
+}
+
+/// release_dispute resolves `amount` of the open dispute of `record` once
+/// its entries are posted.
+fn release_dispute(record: &mut Record, amount: f32) {
+    record.disputed = round(record.disputed - amount, &record.txn.currency);
+    if record.disputed == 0.0 {
+        record.disputed_at = None;
+    }
+}
+
+/// find_record looks up a recorded transaction that `client` took part in.
+fn find_record(records: &mut [Record], tx: u32, client: u16) -> Option<&mut Record> {
+    let pos = records
+        .binary_search_by(|record| record.txn.tx.cmp(&tx))
+        .ok()?;
+    let record = records.get_mut(pos)?;
+    if credits(record, 0.0)
+        .iter()
+        .any(|(party, _)| *party == client)
+    {
+        Some(record)
+    } else {
+        None
+    }
+}
+
+/// find_authorization looks up an authorization of `client` that still has
+/// funds on hold.
+fn find_authorization(records: &mut [Record], tx: u32, client: u16) -> Option<&mut Record> {
+    let pos = records
+        .binary_search_by(|record| record.txn.tx.cmp(&tx))
+        .ok()?;
+    records.get_mut(pos).filter(|record| {
+        record.txn.r#type == "authorize" && record.txn.client == client && record.hold > 0.0
+    })
+}
+
+/// diff describes how the state of a client changed, `before` is unknown
+/// for a client that was not sending the transaction.
+fn diff(before: Option<&Stats>, after: &Stats) -> String {
+    let mut lines = vec![];
+    let flags = |stat: &Stats| (stat.locked, stat.flagged, stat.closed);
+    match before {
+        Some(before) if flags(before) != flags(after) => lines.push(format!(
+            "  locked {} -> {}, flagged {} -> {}, closed {} -> {}",
+            before.locked, after.locked, before.flagged, after.flagged, before.closed, after.closed
+        )),
+        _ => lines.push(format!(
+            "  locked {}, flagged {}, closed {}",
+            after.locked, after.flagged, after.closed
+        )),
+    }
+    let mut currencies: Vec<_> = after.balances.keys().collect();
+    currencies.sort();
+    for currency in currencies {
+        let code = currency.as_deref().unwrap_or("no currency");
+        let now = after.balance(currency);
+        let line = match before.map(|before| before.balance(currency)) {
+            Some(then) => format!(
+                "  {}: available {} -> {}, held {} -> {}, total {} -> {}",
+                code, then.available, now.available, then.held, now.held, then.total, now.total
+            ),
+            None => format!(
+                "  {}: available {}, held {}, total {}",
+                code, now.available, now.held, now.total
+            ),
+        };
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// valid_amount tells if `amount` is a number the engine can work with.
+fn valid_amount(amount: f32) -> bool {
+    amount.is_finite() && amount.abs() <= MAX_AMOUNT
+}
+
+/// credits lists every client a recorded transaction touched together with
+/// `amount` signed as a credit to them, negative when the client was debited.
+/// Disputes, resolves and chargebacks are applied to each of them in turn.
+fn credits(record: &Record, amount: f32) -> Vec<(u16, f32)> {
+    let txn = &record.txn;
+    match (txn.r#type.as_str(), txn.destination) {
+        ("deposit", _) => vec![(txn.client, amount)],
+        ("withdrawal", _) | ("authorize", _) => vec![(txn.client, -amount)],
+        ("transfer", Some(destination)) => vec![(txn.client, -amount), (destination, amount)],
+        _ => vec![],
+    }
+}
+
+/// touches_closed tells if settling `amount` of the dispute of `record` would
+/// move funds of a closed account.
+fn touches_closed(stats: &HashMap<u16, Stats>, record: &Record, amount: f32) -> bool {
+    credits(record, amount)
+        .iter()
+        .any(|(client, _)| stats.get(client).is_some_and(|entry| entry.closed))
+}
+
+/// disputable returns the part of a recorded transaction that is not under
+/// dispute, charged back or refunded yet.
+fn disputable(record: &Record) -> f32 {
+    let amount = match record.txn.r#type.as_str() {
+        // only the captured part of an authorization has left the account
+        "authorize" => record.captured,
+        _ => record.txn.amount.unwrap_or(0.0),
+    };
+    round(
+        amount - record.disputed - record.charged_back - record.refunded,
+        &record.txn.currency,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+
+    use chrono::{DateTime, NaiveDate, TimeDelta};
+
+    use crate::app::{
+        accounts, check, load_opening_balances, replay, state, write_audit, AdminAction, Engine,
+        OutOfOrder, Rejection, Reservation,
+    };
+    use crate::cli_config::CliConfig;
+    use crate::clients;
+    use crate::disputes::DisputeWindows;
+    use crate::exchange::{RateTable, Rounding};
+    use crate::fees::FeeSchedule;
+    use crate::heuristics::Monitor;
+    use crate::ledger::{
+        Entry,
+        LedgerAccount::{Available, Exchange, ExchangeSpread, External, Held},
+    };
+    use crate::operators;
+    use crate::rules::RuleSet;
+
+    use super::Transaction;
+
+    #[test]
+    fn should_deposit_correctly() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 2.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_withdrawal_correctly() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 1,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 4.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 4.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_withdrawal_fail_when_insufficient_fund() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.0),
+                client: 1,
+                tx: 1,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_dispute_deposit_txn_correctly() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 1.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 6.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_dispute_withdrawal_txn_correctly() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, -1.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 4.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_resolve_work_for_deposit_txn() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "resolve".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 7.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 7.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_process_deposit_chargeback() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+        assert!(engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_process_withdrawal_chargeback() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+        assert!(engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_ignore_resolve_if_no_dispute_deposit_txn() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "resolve".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 8.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 8.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_ignore_resolve_if_no_dispute_withdrawal_txn() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "resolve".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 2.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_ignore_chargeback_if_no_dispute_deposit_txn() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 8.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 8.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_ignore_chargeback_if_no_dispute_withdrawal_txn() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 2.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_accept_amount_till_four_digits_for_deposit() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.66666),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.66666),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(
+            engine.stats.get(&1).unwrap().balance(&None).available,
+            11.3334
+        );
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 11.3334);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_accept_amount_till_four_digits_for_withdrawal() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.66666),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.11111),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(
+            engine.stats.get(&1).unwrap().balance(&None).available,
+            0.5556
+        );
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 0.5556);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_load_opening_balances() {
+        let mut engine = Engine::default();
+        let data = "client, available, held, total, locked
+1, 1.5, 0.5, 2.0, false
+2, 0.0, 0.0, 0.0, true
+";
+        load_opening_balances(data.as_bytes(), &mut engine).unwrap();
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 1.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 2.0);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+        assert!(engine.stats.get(&2).unwrap().locked);
+    }
+
+    #[test]
+    fn should_reject_inconsistent_opening_balances() {
+        let mut engine = Engine::default();
+        let data = "client,available,held,total,locked
+1,1.5,0.5,3.0,false
+";
+        assert!(load_opening_balances(data.as_bytes(), &mut engine).is_err());
+    }
+
+    #[test]
+    fn should_reject_duplicate_opening_balances() {
+        let mut engine = Engine::default();
+        let data = "client,available,held,total,locked
+1,1.0,0.0,1.0,false
+1,2.0,0.0,2.0,false
+";
+        assert!(load_opening_balances(data.as_bytes(), &mut engine).is_err());
+    }
+
+    #[test]
+    fn should_continue_from_opening_balances() {
+        let mut engine = Engine::default();
+        let data = "client,available,held,total,locked
+1,3.0,1.0,4.0,false
+";
+        load_opening_balances(data.as_bytes(), &mut engine).unwrap();
+        let txns = vec![
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(4.5),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 0.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 1.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 1.5);
+        assert!(!engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_transfer_between_clients() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "transfer".to_string(),
+                destination: Some(2),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).available, 2.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 2.0);
+    }
+
+    #[test]
+    fn should_transfer_fail_when_insufficient_fund() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.0),
+                client: 1,
+                tx: 2,
+                r#type: "transfer".to_string(),
+                destination: Some(2),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+        assert!(!engine.stats.contains_key(&2));
+    }
+
+    #[test]
+    fn should_transfer_fail_when_account_locked() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 2,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 2,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 2,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 3,
+                r#type: "transfer".to_string(),
+                destination: Some(2),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 2,
+                tx: 4,
+                r#type: "transfer".to_string(),
+                destination: Some(1),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 0.0);
+        assert!(engine.stats.get(&2).unwrap().locked);
+    }
+
+    #[test]
+    fn should_dispute_transfer_on_both_sides() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "transfer".to_string(),
+                destination: Some(2),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 2,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, -2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).available, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).held, 2.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 2.0);
+    }
+
+    #[test]
+    fn should_reverse_transfer_on_chargeback() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "transfer".to_string(),
+                destination: Some(2),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+        assert!(engine.stats.get(&1).unwrap().locked);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).available, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 0.0);
+        assert!(!engine.stats.get(&2).unwrap().locked);
+    }
+
+    #[test]
+    fn should_ignore_dispute_from_unrelated_client() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 2,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 2,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).available, 2.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).held, 0.0);
+    }
+
+    #[test]
+    fn should_hold_funds_on_authorize() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_authorize_fail_when_insufficient_available_fund() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 3,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_capture_full_authorization() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "capture".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.0);
+    }
+
+    #[test]
+    fn should_release_remainder_on_partial_capture() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.5),
+                client: 1,
+                tx: 2,
+                r#type: "capture".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.5);
+    }
+
+    #[test]
+    fn should_ignore_capture_above_authorization() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.5),
+                client: 1,
+                tx: 2,
+                r#type: "capture".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_release_hold_on_void() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "void".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "capture".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_ignore_void_after_capture() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 2,
+                r#type: "capture".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "void".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 4.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 4.0);
+    }
+
+    #[test]
+    fn should_dispute_captured_part_of_authorization() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 2,
+                r#type: "capture".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+        assert!(engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_hold_only_disputed_part_of_deposit() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_add_up_partial_disputes() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(0.5),
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_ignore_dispute_above_remaining_amount() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.5),
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_ignore_second_full_dispute() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_resolve_part_of_dispute() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.5),
+                client: 1,
+                tx: 1,
+                r#type: "resolve".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 1.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 3.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_charge_back_part_of_dispute() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(4.0),
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 1,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "resolve".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 4.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 4.0);
+        assert!(engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_not_redispute_charged_back_part() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.0);
+    }
+
+    #[test]
+    fn should_refund_part_of_deposit() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "refund".to_string(),
+                reference: Some(1),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.0);
+    }
+
+    #[test]
+    fn should_ignore_refund_above_refundable_amount() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(4.0),
+                client: 1,
+                tx: 3,
+                r#type: "refund".to_string(),
+                reference: Some(1),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.5),
+                client: 1,
+                tx: 4,
+                r#type: "refund".to_string(),
+                reference: Some(1),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 4.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 4.0);
+    }
+
+    #[test]
+    fn should_ignore_refund_of_withdrawal() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 3,
+                r#type: "refund".to_string(),
+                reference: Some(2),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.0);
+    }
+
+    #[test]
+    fn should_ignore_refund_of_other_clients_deposit() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 2,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 2,
+                tx: 3,
+                r#type: "refund".to_string(),
+                reference: Some(1),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_dispute_only_remaining_amount_of_refunded_deposit() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.5),
+                client: 1,
+                tx: 3,
+                r#type: "refund".to_string(),
+                reference: Some(2),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.5);
+    }
+
+    #[test]
+    fn should_ignore_refund_of_disputed_amount() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(4.0),
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "refund".to_string(),
+                reference: Some(1),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 1.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 4.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_keep_balances_per_currency() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 3,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 4,
+                r#type: "withdrawal".to_string(),
+                currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let usd = Some("USD".to_string());
+        let eur = Some("EUR".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).total, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).available, 1.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).total, 1.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 1.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 1.0);
+    }
+
+    #[test]
+    fn should_round_to_currency_precision() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.666),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(100.6),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                currency: Some("JPY".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(0.0005),
+                client: 1,
+                tx: 3,
+                r#type: "deposit".to_string(),
+                currency: Some("KWD".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let usd = Some("USD".to_string());
+        let jpy = Some("JPY".to_string());
+        let kwd = Some("KWD".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).total, 5.67);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&jpy).total, 101.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&kwd).total, 0.001);
+    }
+
+    #[test]
+    fn should_ignore_unknown_currency() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("ABC".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                currency: Some("usd".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert!(!engine.stats.contains_key(&1));
+    }
+
+    #[test]
+    fn should_not_withdraw_from_other_currency() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 3,
+                r#type: "transfer".to_string(),
+                currency: Some("EUR".to_string()),
+                destination: Some(2),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let usd = Some("USD".to_string());
+        let eur = Some("EUR".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).total, 5.0);
+        assert!(!engine.stats.get(&1).unwrap().balances.contains_key(&eur));
+        assert!(!engine.stats.contains_key(&2));
+    }
+
+    #[test]
+    fn should_dispute_in_currency_of_original_transaction() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let usd = Some("USD".to_string());
+        let eur = Some("EUR".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).total, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).available, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).held, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).total, 2.0);
+        assert!(engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_transfer_in_currency() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "transfer".to_string(),
+                currency: Some("EUR".to_string()),
+                destination: Some(2),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let usd = Some("USD".to_string());
+        let eur = Some("EUR".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).available, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).total, 3.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&eur).available, 2.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&eur).held, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&eur).total, 2.0);
+        assert!(!engine.stats.get(&2).unwrap().balances.contains_key(&usd));
+    }
+
+    #[test]
+    fn should_output_row_per_client_currency() {
+        let mut engine = Engine::default();
+        let data = "client,currency,available,held,total,locked
+1,USD,1.5,0.5,2.0,false
+1,EUR,3.0,0.0,3.0,true
+2,,1.0,0.0,1.0,false
+";
+        load_opening_balances(data.as_bytes(), &mut engine).unwrap();
+
+        let rows: Vec<_> = accounts(engine.stats.get(&1).unwrap(), &engine.clients).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].currency, Some("EUR".to_string()));
+        assert_eq!(rows[0].total, 3.0);
+        assert_eq!(rows[1].currency, Some("USD".to_string()));
+        assert_eq!(rows[1].available, 1.5);
+        assert!(rows.iter().all(|row| row.locked));
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 1.0);
+    }
+
+    #[test]
+    fn should_reject_opening_balances_in_unknown_currency() {
+        let mut engine = Engine::default();
+        let data = "client,currency,available,held,total,locked
+1,XYZ,1.0,0.0,1.0,false
+";
+        assert!(load_opening_balances(data.as_bytes(), &mut engine).is_err());
+    }
+
+    const RATES: &str = "from,to,rate,spread,effective
+USD,EUR,0.9,0.01,2022-01-01
+USD,EUR,0.8,0.01,2022-02-01
+EUR,JPY,130.0,0.0,2022-01-01
+";
+
+    #[test]
+    fn should_exchange_at_latest_rate() {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(20.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(10.01),
+                client: 1,
+                tx: 2,
+                r#type: "exchange".to_string(),
+                currency: Some("USD".to_string()),
+                to_currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let usd = Some("USD".to_string());
+        let eur = Some("EUR".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).available, 9.99);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).total, 9.99);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).available, 7.93);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).total, 7.93);
+        assert_eq!(engine.house.exchange_spread[&eur], 0.08);
+    }
+
+    #[test]
+    fn should_exchange_at_rate_in_effect_on_rates_date() {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            rates_date: NaiveDate::parse_from_str("2022-01-20", "%Y-%m-%d").ok(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(20.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 2,
+                r#type: "exchange".to_string(),
+                currency: Some("USD".to_string()),
+                to_currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let eur = Some("EUR".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).available, 8.91);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).total, 8.91);
+        assert_eq!(engine.house.exchange_spread[&eur], 0.09);
+    }
+
+    #[test]
+    fn should_round_exchange_as_configured() {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            exchange_rounding: Rounding::Down,
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(20.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(10.01),
+                client: 1,
+                tx: 2,
+                r#type: "exchange".to_string(),
+                currency: Some("USD".to_string()),
+                to_currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let eur = Some("EUR".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).available, 7.92);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).total, 7.92);
+        assert_eq!(engine.house.exchange_spread[&eur], 0.09);
+    }
+
+    #[test]
+    fn should_round_exchange_to_target_currency_precision() {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(20.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.23),
+                client: 1,
+                tx: 2,
+                r#type: "exchange".to_string(),
+                currency: Some("EUR".to_string()),
+                to_currency: Some("JPY".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let jpy = Some("JPY".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&jpy).available, 160.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&jpy).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&jpy).total, 160.0);
+    }
+
+    #[test]
+    fn should_ignore_exchange_without_rate() {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(20.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 2,
+                r#type: "exchange".to_string(),
+                currency: Some("EUR".to_string()),
+                to_currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 3,
+                r#type: "exchange".to_string(),
+                currency: Some("USD".to_string()),
+                to_currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let eur = Some("EUR".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).available, 20.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).total, 20.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balances.len(), 1);
+    }
+
+    #[test]
+    fn should_exchange_fail_when_insufficient_fund() {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.0),
+                client: 1,
+                tx: 2,
+                r#type: "exchange".to_string(),
+                currency: Some("USD".to_string()),
+                to_currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let usd = Some("USD".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).total, 5.0);
+        assert!(engine.house.exchange_spread.is_empty());
+    }
+
+    const FEES: &str = "kind,tier,currency,min_amount,flat,rate,cap
+deposit,,,,0.5,,
+withdrawal,,,,1.0,0.01,2.0
+withdrawal,premium,,,,,
+transfer,,,,0.25,,
+";
+
+    const CLIENTS: &str = "client,tier
+2,premium
+";
+
+    #[test]
+    fn should_charge_deposit_fee() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![Transaction {
+            amount: Some(10.0),
+            client: 1,
+            tx: 1,
+            r#type: "deposit".to_string(),
+            ..Default::default()
+        }];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 9.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 9.5);
+        assert_eq!(engine.house.fees[&None], 0.5);
+    }
+
+    #[test]
+    fn should_reject_deposit_smaller_than_fee() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let deposit = |tx, amount| Transaction {
+            amount: Some(amount),
+            client: 1,
+            tx,
+            r#type: "deposit".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            engine.process_txn(deposit(1, 0.2)),
+            Err(Rejection::InsufficientFunds)
+        );
+        assert!(engine.house.fees.is_empty());
+
+        // the rest of the fee comes out of the funds already there
+        assert_eq!(engine.process_txn(deposit(2, 10.0)), Ok(()));
+        assert_eq!(engine.process_txn(deposit(3, 0.2)), Ok(()));
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 9.2);
+        assert_eq!(engine.house.fees[&None], 1.0);
+    }
+
+    #[test]
+    fn should_reject_deposit_of_negative_amount() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let deposit = |tx, amount| Transaction {
+            amount: Some(amount),
+            client: 1,
+            tx,
+            r#type: "deposit".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(engine.process_txn(deposit(1, 1000.0)), Ok(()));
+        // would take funds out without the rules and fee of a withdrawal
+        assert_eq!(
+            engine.process_txn(deposit(2, -500.0)),
+            Err(Rejection::InvalidAmount)
+        );
+        assert_eq!(
+            engine.process_txn(deposit(3, 0.0)),
+            Err(Rejection::InvalidAmount)
+        );
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 999.5);
+    }
+
+    #[test]
+    fn should_reject_withdrawal_of_negative_amount() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txn = |tx, r#type: &str, amount| Transaction {
+            amount: Some(amount),
+            client: 1,
+            tx,
+            r#type: r#type.to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(engine.process_txn(txn(1, "deposit", 10.0)), Ok(()));
+        // credits the account without the fee of a deposit
+        assert_eq!(
+            engine.process_txn(txn(2, "withdrawal", -100.0)),
+            Err(Rejection::InvalidAmount)
+        );
+        assert_eq!(
+            engine.process_txn(txn(3, "withdrawal", 0.0)),
+            Err(Rejection::InvalidAmount)
+        );
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 9.5);
+        assert_eq!(engine.house.fees[&None], 0.5);
+    }
+
+    #[test]
+    fn should_charge_withdrawal_fee() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 3.45);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.45);
+        assert_eq!(engine.house.fees[&None], 1.55);
+    }
+
+    #[test]
+    fn should_withdrawal_fail_when_fee_not_covered() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(8.5),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 9.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 9.5);
+        assert_eq!(engine.house.fees[&None], 0.5);
+    }
+
+    #[test]
+    fn should_charge_fee_of_client_tier() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            clients: clients::load_clients(CLIENTS.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(10.0),
+                client: 2,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 2,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).available, 4.5);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 4.5);
+        assert_eq!(engine.house.fees[&None], 0.5);
+    }
+
+    #[test]
+    fn should_charge_transfer_fee_to_sender() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 2,
+                r#type: "transfer".to_string(),
+                destination: Some(2),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 4.25);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 4.25);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 5.0);
+        assert_eq!(engine.house.fees[&None], 0.75);
+    }
+
+    #[test]
+    fn should_refund_deposit_fee_on_chargeback() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(4.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 9.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 9.5);
+        assert_eq!(engine.house.fees[&None], 0.5);
+        assert!(engine.stats.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn should_refund_deposit_fee_in_proportion_to_partial_chargebacks() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(4.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 9.5);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 9.5);
+        assert_eq!(engine.house.fees[&None], 0.5);
+    }
+
+    const CREDIT_LINES: &str = "client,credit_limit,currency
+1,10.0,
+2,10.0,USD
+";
+
+    #[test]
+    fn should_overdraw_within_credit_limit() {
+        let mut engine = Engine {
+            clients: clients::load_clients(CREDIT_LINES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(12.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, -7.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, -7.0);
+        let rows: Vec<_> = accounts(engine.stats.get(&1).unwrap(), &engine.clients).collect();
+        assert_eq!(rows[0].credit_used, 7.0);
+    }
+
+    #[test]
+    fn should_deposit_to_account_below_zero() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 3,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+        ];
+        let results: Vec<_> = txns
+            .into_iter()
+            .map(|txn| engine.process_txn(txn))
+            .collect();
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, -5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 10.0);
+    }
+
+    #[test]
+    fn should_report_credit_used_up_to_the_limit() {
+        let mut engine = Engine {
+            clients: clients::load_clients(CREDIT_LINES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let usd = Some("USD".to_string());
+        let txn = |r#type: &str, tx, amount, currency: &Option<String>| Transaction {
+            amount,
+            client: 1,
+            tx,
+            r#type: r#type.to_string(),
+            currency: currency.clone(),
+            ..Default::default()
+        };
+        // spent deposits that are disputed leave the balances below zero,
+        // beyond the credit line of 10 and outside its currency
+        let txns = vec![
+            txn("deposit", 1, Some(20.0), &None),
+            txn("withdrawal", 2, Some(20.0), &None),
+            txn("dispute", 1, None, &None),
+            txn("deposit", 3, Some(5.0), &usd),
+            txn("withdrawal", 4, Some(5.0), &usd),
+            txn("dispute", 3, None, &usd),
+        ];
+        for txn in txns {
+            assert_eq!(engine.process_txn(txn), Ok(()));
+        }
+
+        let rows: Vec<_> = accounts(engine.stats.get(&1).unwrap(), &engine.clients).collect();
+        assert_eq!(rows[0].currency, None);
+        assert_eq!(rows[0].available, -20.0);
+        assert_eq!(rows[0].credit_used, 10.0);
+        assert_eq!(rows[1].currency, usd);
+        assert_eq!(rows[1].available, -5.0);
+        assert_eq!(rows[1].credit_used, 0.0);
+    }
+
+    #[test]
+    fn should_withdrawal_fail_beyond_credit_limit() {
+        let mut engine = Engine {
+            clients: clients::load_clients(CREDIT_LINES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(12.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.5),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, -7.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, -7.0);
+    }
+
+    #[test]
+    fn should_withdraw_on_credit_in_currency_without_balance() {
+        let mut engine = Engine {
+            clients: clients::load_clients(CREDIT_LINES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let usd = Some("USD".to_string());
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 2,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(4.0),
+                client: 2,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                currency: usd.clone(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            assert_eq!(engine.process_txn(txn), Ok(()));
+        }
+
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&usd).available, -4.0);
+    }
+
+    #[test]
+    fn should_apply_credit_limit_only_in_its_currency() {
+        let mut engine = Engine {
+            clients: clients::load_clients(CREDIT_LINES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 2,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.0),
+                client: 2,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 2,
+                tx: 3,
+                r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.0),
+                client: 2,
+                tx: 4,
+                r#type: "withdrawal".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 5.0);
+        assert_eq!(
+            engine
+                .stats
+                .get(&2)
+                .unwrap()
+                .balance(&Some("USD".to_string()))
+                .available,
+            -1.0
+        );
+        assert_eq!(
+            engine
+                .stats
+                .get(&2)
+                .unwrap()
+                .balance(&Some("USD".to_string()))
+                .held,
+            0.0
+        );
+        assert_eq!(
+            engine
+                .stats
+                .get(&2)
+                .unwrap()
+                .balance(&Some("USD".to_string()))
+                .total,
+            -1.0
+        );
+    }
+
+    #[test]
+    fn should_transfer_on_credit() {
+        let mut engine = Engine {
+            clients: clients::load_clients(CREDIT_LINES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(8.0),
+                client: 1,
+                tx: 2,
+                r#type: "transfer".to_string(),
+                destination: Some(3),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, -3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, -3.0);
+        assert_eq!(engine.stats.get(&3).unwrap().balance(&None).available, 8.0);
+        assert_eq!(engine.stats.get(&3).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&3).unwrap().balance(&None).total, 8.0);
+    }
+
+    #[test]
+    fn should_not_withdraw_held_funds() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.0),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 8.0);
+    }
+
+    #[test]
+    fn should_withdraw_available_funds_next_to_held_funds() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.0);
+    }
+
+    #[test]
+    fn should_not_transfer_held_funds() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.0),
+                client: 1,
+                tx: 3,
+                r#type: "transfer".to_string(),
+                destination: Some(2),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 8.0);
+        assert!(!engine.stats.contains_key(&2));
+    }
+
+    #[test]
+    fn should_not_authorize_held_funds() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.0),
+                client: 1,
+                tx: 3,
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 8.0);
+    }
+
+    #[test]
+    fn should_withdraw_only_released_part_of_held_funds() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 2,
+                r#type: "resolve".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.5),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(6.0),
+                client: 1,
+                tx: 4,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 2.0);
+    }
+
+    #[test]
+    fn should_withdraw_held_funds_with_total_reservation() {
+        let mut engine = Engine {
+            reservation: Reservation::Total,
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(7.0),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, -2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 1.0);
+    }
+
+    #[test]
+    fn should_not_spend_disputed_withdrawal_with_strict_reservation() {
+        let mut engine = Engine {
+            reservation: Reservation::Strict,
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(4.0),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, -2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.0);
+    }
+
+    #[test]
+    fn should_spend_disputed_withdrawal_with_available_reservation() {
+        let mut engine = Engine::default();
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(4.0),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 1.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, -2.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, -1.0);
+    }
+
+    #[test]
+    fn should_spend_deposit_with_strict_reservation() {
+        let mut engine = Engine {
+            reservation: Reservation::Strict,
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 3.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 3.0);
+    }
+
+    const RULES: &str = "rule,limit,window,currency
+max_withdrawal,100,,
+max_withdrawal_rolling,120,2,
+max_disputes,1,,
+";
+
+    #[test]
+    fn should_report_why_transaction_was_rejected() {
+        let mut engine = Engine::default();
+        let deposit = Transaction {
+            amount: Some(1.0),
+            client: 1,
+            tx: 1,
+            r#type: "deposit".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(engine.process_txn(deposit), Ok(()));
+
+        let withdrawal = Transaction {
+            amount: Some(2.0),
+            client: 1,
+            tx: 2,
+            r#type: "withdrawal".to_string(),
+            ..Default::default()
+        };
+        let resolve = Transaction {
+            client: 1,
+            tx: 1,
+            r#type: "resolve".to_string(),
+            ..Default::default()
+        };
+        let unknown = Transaction {
+            client: 1,
+            tx: 3,
+            r#type: "deposits".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            engine.process_txn(withdrawal),
+            Err(Rejection::InsufficientFunds)
+        );
+        assert_eq!(engine.process_txn(resolve), Err(Rejection::NotDisputed));
+        assert_eq!(engine.process_txn(unknown), Err(Rejection::UnknownType));
+    }
+
+    #[test]
+    fn should_reject_transaction_breaking_rule() {
+        let mut engine = Engine {
+            rules: RuleSet::load(RULES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(500.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(80.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(120.0),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(50.0),
+                client: 1,
+                tx: 4,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+        ];
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
+
+        assert_eq!(results[1], Ok(()));
+        assert_eq!(
+            results[2],
+            Err(Rejection::Rule("max_withdrawal 100".to_string()))
+        );
+        assert_eq!(
+            results[3],
+            Err(Rejection::Rule("max_withdrawal_rolling 120".to_string()))
+        );
+        assert_eq!(
+            engine.stats.get(&1).unwrap().balance(&None).available,
+            420.0
+        );
+    }
+
+    #[test]
+    fn should_only_count_applied_disputes_towards_rules() {
+        let mut engine = Engine {
+            rules: RuleSet::load(RULES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(10.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                client: 1,
+                tx: 2,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                client: 1,
+                tx: 1,
+                r#type: "resolve".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+        ];
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
+
+        assert_eq!(results[1], Err(Rejection::UnknownTransaction));
+        assert_eq!(results[2], Ok(()));
+        assert_eq!(
+            results[4],
+            Err(Rejection::Rule("max_disputes 1".to_string()))
+        );
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+    }
+
+    const HEURISTICS: &str = "heuristic,threshold,action
+withdrawal_after_deposit,0.9,lock
+";
+
+    #[test]
+    fn should_lock_client_caught_by_heuristic() {
+        let mut engine = Engine {
+            monitor: Monitor::load(HEURISTICS.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(100.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(95.0),
+                client: 1,
+                tx: 2,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(10.0),
+                client: 2,
+                tx: 3,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+        ];
+        for txn in txns {
+            let _ = engine.apply(txn);
+        }
+
+        let stats = engine.stats.get(&1).unwrap();
+        assert!(stats.flagged);
+        assert!(stats.locked);
+        assert!(!engine.stats.get(&2).unwrap().flagged);
+        assert_eq!(engine.flags.len(), 1);
+        assert_eq!(engine.flags[0].heuristic, "withdrawal_after_deposit");
+        assert!(accounts(stats, &engine.clients).next().unwrap().flagged);
+    }
+
+    #[test]
+    fn should_unlock_and_freeze_account_by_operator() {
+        let mut engine = Engine {
+            operators: operators::load_operators("operator\nalice\n".as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "unlock".to_string(),
+                operator: Some("alice".to_string()),
+                reason: Some("manual review".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 3,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 3,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 4,
+                r#type: "freeze".to_string(),
+                operator: Some("alice".to_string()),
+                reason: Some("manual review".to_string()),
+                ..Default::default()
+            },
+        ];
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
+
+        assert_eq!(results[3], Ok(()));
+        assert_eq!(results[6], Ok(()));
+        let stats = engine.stats.get(&1).unwrap();
+        assert!(stats.locked);
+        assert_eq!(stats.balance(&None).held, 2.0);
+        assert_eq!(stats.balance(&None).total, 2.0);
+    }
+
+    #[test]
+    fn should_refuse_deposits_and_withdrawals_of_locked_account() {
+        let mut engine = Engine {
+            operators: operators::load_operators("operator\nalice\n".as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txn = |r#type: &str, tx, amount| Transaction {
+            amount,
+            client: 1,
+            tx,
+            r#type: r#type.to_string(),
+            operator: Some("alice".to_string()),
+            reason: Some("manual review".to_string()),
+            ..Default::default()
+        };
+        let txns = vec![
+            txn("deposit", 1, Some(10.0)),
+            txn("freeze", 2, None),
+            txn("deposit", 3, Some(5.0)),
+            txn("withdrawal", 4, Some(5.0)),
+            // disputes still apply, for a frozen account to be settled
+            txn("dispute", 1, None),
+            txn("resolve", 1, None),
+            txn("unlock", 5, None),
+            txn("withdrawal", 6, Some(5.0)),
+        ];
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
+
+        assert_eq!(results[2], Err(Rejection::AccountLocked));
+        assert_eq!(results[3], Err(Rejection::AccountLocked));
+        assert!(results[4..].iter().all(Result::is_ok));
+        let stats = engine.stats.get(&1).unwrap();
+        assert!(!stats.locked);
+        assert_eq!(stats.balance(&None).total, 5.0);
+    }
+
+    #[test]
+    fn should_audit_admin_transactions() {
+        let mut engine = Engine {
+            operators: operators::load_operators("operator\nalice\nbob\n".as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let admin = |r#type: &str, tx, operator: &str, reason: &str| Transaction {
+            client: 1,
+            tx,
+            r#type: r#type.to_string(),
+            operator: Some(operator.to_string()),
+            reason: Some(reason.to_string()),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            admin("freeze", 2, "alice", "card reported stolen"),
+            // rejected ones are in the rejections, not the audit
+            admin("unlock", 3, "mallory", "please"),
+            admin("unlock", 4, "bob", "card replaced"),
+        ];
+        for txn in txns {
+            let _ = engine.apply(txn);
+        }
+        let mut audit = vec![];
+        write_audit(&mut audit, &engine.audit).unwrap();
+
+        let mut rdr = csv::Reader::from_reader(audit.as_slice());
+        let actions: Vec<AdminAction> = rdr.deserialize().map(Result::unwrap).collect();
+        assert_eq!(
+            actions,
+            vec![
+                AdminAction {
+                    tx: 2,
+                    client: 1,
+                    action: "freeze".to_string(),
+                    operator: "alice".to_string(),
+                    reason: "card reported stolen".to_string(),
+                },
+                AdminAction {
+                    tx: 4,
+                    client: 1,
+                    action: "unlock".to_string(),
+                    operator: "bob".to_string(),
+                    reason: "card replaced".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            String::from_utf8(audit).unwrap().lines().next(),
+            Some("tx,client,action,operator,reason")
+        );
+    }
+
+    #[test]
+    fn should_reject_admin_transaction_of_unknown_operator() {
+        let mut engine = Engine {
+            operators: operators::load_operators("operator\nalice\n".as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txns = vec![
+            Transaction {
+                amount: Some(1.0),
+                client: 1,
+                tx: 1,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 2,
+                r#type: "freeze".to_string(),
+                operator: Some("mallory".to_string()),
+                reason: Some("manual review".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 3,
+                r#type: "unlock".to_string(),
+                operator: Some("mallory".to_string()),
+                reason: Some("manual review".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                client: 1,
+                tx: 4,
+                r#type: "unlock".to_string(),
+                operator: Some("alice".to_string()),
+                ..Default::default()
+            },
+        ];
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
 
-    use super::Transaction;
+        assert_eq!(results[1], Err(Rejection::Unauthorized));
+        assert_eq!(results[4], Err(Rejection::Unauthorized));
+        assert_eq!(results[5], Err(Rejection::MissingReason));
+        assert!(engine.stats.get(&1).unwrap().locked);
+    }
 
     #[test]
-    fn should_deposit_correctly() {
-        let mut stats = HashMap::new();
+    fn should_close_account_without_balance() {
+        let mut engine = Engine {
+            operators: operators::load_operators("operator\nalice\n".as_bytes()).unwrap(),
+            ..Default::default()
+        };
         let txns = vec![
             Transaction {
-                amount: Some(1.0),
+                amount: Some(2.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 2,
+                tx: 2,
+                r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 3,
+                r#type: "close".to_string(),
+                operator: Some("alice".to_string()),
+                reason: Some("manual review".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 1,
+                tx: 4,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 5,
+                r#type: "close".to_string(),
+                operator: Some("alice".to_string()),
+                reason: Some("manual review".to_string()),
+                ..Default::default()
             },
             Transaction {
                 amount: Some(1.0),
                 client: 1,
-                tx: 1,
+                tx: 6,
                 r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 2,
+                tx: 7,
+                r#type: "transfer".to_string(),
+                destination: Some(1),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 8,
+                r#type: "unlock".to_string(),
+                operator: Some("alice".to_string()),
+                reason: Some("manual review".to_string()),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
-        for txn in txns {
-            process_txn(txn, records, &mut stats)
-        }
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
 
-        assert_eq!(stats.get(&1).unwrap().available, 2.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 2.0);
-        assert!(!stats.get(&1).unwrap().locked);
+        assert_eq!(results[2], Err(Rejection::BalanceNotZero));
+        assert_eq!(results[4], Ok(()));
+        assert_eq!(results[5], Err(Rejection::AccountClosed));
+        assert_eq!(results[6], Err(Rejection::AccountClosed));
+        assert_eq!(results[7], Err(Rejection::AccountClosed));
+        let stats = engine.stats.get(&1).unwrap();
+        assert!(stats.closed);
+        assert_eq!(stats.balance(&None).total, 0.0);
+        assert_eq!(engine.stats.get(&2).unwrap().balance(&None).total, 5.0);
     }
 
     #[test]
-    fn should_withdrawal_correctly() {
-        let mut stats = HashMap::new();
+    fn should_not_dispute_into_closed_account() {
+        let mut engine = Engine {
+            operators: operators::load_operators("operator\nalice\n".as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let txn = |r#type: &str, client, tx, amount| Transaction {
+            amount,
+            client,
+            tx,
+            r#type: r#type.to_string(),
+            ..Default::default()
+        };
+        let mut transfer = txn("transfer", 1, 2, Some(5.0));
+        transfer.destination = Some(2);
+        let mut close = txn("close", 2, 4, None);
+        close.operator = Some("alice".to_string());
+        close.reason = Some("manual review".to_string());
+        let txns = vec![
+            txn("deposit", 1, 1, Some(10.0)),
+            transfer,
+            txn("withdrawal", 2, 3, Some(5.0)),
+            close,
+            // the transfer took funds from client 2, which is closed now
+            txn("dispute", 1, 2, None),
+            txn("chargeback", 1, 2, None),
+            txn("resolve", 1, 2, None),
+        ];
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
+
+        assert!(results[..4].iter().all(Result::is_ok));
+        assert_eq!(results[4], Err(Rejection::AccountClosed));
+        assert_eq!(results[5], Err(Rejection::NotDisputed));
+        assert_eq!(results[6], Err(Rejection::NotDisputed));
+        let stats = engine.stats.get(&2).unwrap();
+        assert!(stats.closed);
+        assert_eq!(stats.balance(&None).available, 0.0);
+        assert_eq!(stats.balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 5.0);
+        assert!(engine.check_ledger().is_ok());
+    }
+
+    #[test]
+    fn should_reject_out_of_order_transaction() {
+        let mut engine = Engine {
+            out_of_order: OutOfOrder::Reject,
+            ..Default::default()
+        };
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(10.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
                 amount: Some(1.0),
                 client: 1,
-                tx: 1,
+                tx: 2,
                 r#type: "withdrawal".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T09:00:00Z").ok(),
+                ..Default::default()
             },
-        ];
-        let records = &mut vec![];
-        for txn in txns {
-            process_txn(txn, records, &mut stats)
-        }
-
-        assert_eq!(stats.get(&1).unwrap().available, 4.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 4.0);
-        assert!(!stats.get(&1).unwrap().locked);
-    }
-
-    #[test]
-    fn should_withdrawal_fail_when_insufficient_fund() {
-        let mut stats = HashMap::new();
-        let txns = vec![
             Transaction {
-                amount: Some(5.0),
-                client: 1,
-                tx: 1,
+                amount: Some(1.0),
+                client: 2,
+                tx: 3,
                 r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T09:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(6.0),
+                amount: Some(2.0),
                 client: 1,
-                tx: 1,
+                tx: 4,
+                r#type: "withdrawal".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T12:00:00+02:00").ok(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(3.0),
+                client: 1,
+                tx: 5,
                 r#type: "withdrawal".to_string(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
-        for txn in txns {
-            process_txn(txn, records, &mut stats)
-        }
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
 
-        assert_eq!(stats.get(&1).unwrap().available, 5.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 5.0);
-        assert!(!stats.get(&1).unwrap().locked);
+        assert_eq!(results[1], Err(Rejection::OutOfOrder));
+        // clients are ordered on their own, and the same time in another
+        // offset is not out of order
+        assert_eq!(results[2], Ok(()));
+        assert_eq!(results[3], Ok(()));
+        assert_eq!(results[4], Ok(()));
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 5.0);
     }
 
     #[test]
-    fn should_dispute_deposit_txn_correctly() {
-        let mut stats = HashMap::new();
+    fn should_accept_out_of_order_transaction_by_default() {
+        let mut engine = Engine::default();
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(10.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
                 amount: Some(1.0),
                 client: 1,
                 tx: 2,
-                r#type: "deposit".to_string(),
+                r#type: "withdrawal".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T09:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
-                amount: None,
+                amount: Some(2.0),
                 client: 1,
-                tx: 2,
-                r#type: "dispute".to_string(),
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-02T00:00:00Z").ok(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
         for txn in txns {
-            process_txn(txn, records, &mut stats)
+            assert_eq!(engine.apply(txn), Ok(()));
         }
 
-        assert_eq!(stats.get(&1).unwrap().available, 5.0);
-        assert_eq!(stats.get(&1).unwrap().held, 1.0);
-        assert_eq!(stats.get(&1).unwrap().total, 6.0);
-        assert!(!stats.get(&1).unwrap().locked);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 7.0);
     }
 
     #[test]
-    fn should_dispute_withdrawal_txn_correctly() {
-        let mut stats = HashMap::new();
+    fn should_not_apply_transaction_after_as_of_time() {
+        let mut engine = Engine {
+            as_of: DateTime::parse_from_rfc3339("2022-01-01T23:59:59Z").ok(),
+            ..Default::default()
+        };
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(10.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
                 amount: Some(1.0),
                 client: 1,
                 tx: 2,
                 r#type: "withdrawal".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T09:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
-                amount: None,
+                amount: Some(2.0),
                 client: 1,
-                tx: 2,
-                r#type: "dispute".to_string(),
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-02T00:00:00Z").ok(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
-        for txn in txns {
-            process_txn(txn, records, &mut stats)
-        }
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
 
-        assert_eq!(stats.get(&1).unwrap().available, 5.0);
-        assert_eq!(stats.get(&1).unwrap().held, -1.0);
-        assert_eq!(stats.get(&1).unwrap().total, 4.0);
-        assert!(!stats.get(&1).unwrap().locked);
+        assert_eq!(results[2], Err(Rejection::AfterAsOf));
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 9.0);
     }
 
     #[test]
-    fn should_resolve_work_for_deposit_txn() {
-        let mut stats = HashMap::new();
+    fn should_exchange_at_rate_of_transaction_day() {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(100.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(2.0),
-                client: 1,
-                tx: 2,
-                r#type: "deposit".to_string(),
-            },
-            Transaction {
-                amount: None,
+                amount: Some(10.0),
                 client: 1,
                 tx: 2,
-                r#type: "dispute".to_string(),
+                r#type: "exchange".to_string(),
+                currency: Some("USD".to_string()),
+                to_currency: Some("EUR".to_string()),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-20T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
-                amount: None,
+                amount: Some(10.0),
                 client: 1,
-                tx: 2,
-                r#type: "resolve".to_string(),
+                tx: 3,
+                r#type: "exchange".to_string(),
+                currency: Some("USD".to_string()),
+                to_currency: Some("EUR".to_string()),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
         for txn in txns {
-            process_txn(txn, records, &mut stats);
+            let _ = engine.process_txn(txn);
         }
 
-        assert_eq!(stats.get(&1).unwrap().available, 7.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 7.0);
-        assert!(!stats.get(&1).unwrap().locked);
+        let eur = Some("EUR".to_string());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&eur).available, 16.83);
     }
 
+    const DISPUTE_WINDOWS: &str = "kind,max_age_days,resolve_after_days
+deposit,30,7
+";
+
     #[test]
-    fn should_process_deposit_chargeback() {
-        let mut stats = HashMap::new();
+    fn should_reject_dispute_after_dispute_window() {
+        let mut engine = Engine {
+            dispute_windows: DisputeWindows::load(DISPUTE_WINDOWS.as_bytes()).unwrap(),
+            ..Default::default()
+        };
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(10.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(2.0),
+                amount: Some(5.0),
                 client: 1,
                 tx: 2,
                 r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-03-01T10:00:00Z").ok(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-03-03T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
                 amount: None,
                 client: 1,
                 tx: 2,
                 r#type: "dispute".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-03-03T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
                 amount: None,
                 client: 1,
                 tx: 2,
-                r#type: "chargeback".to_string(),
+                r#type: "resolve".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-03-04T10:00:00Z").ok(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
-        for txn in txns {
-            process_txn(txn, records, &mut stats);
-        }
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
 
-        assert_eq!(stats.get(&1).unwrap().available, 5.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 5.0);
-        assert!(stats.get(&1).unwrap().locked);
+        assert_eq!(results[2], Err(Rejection::DisputeExpired));
+        assert_eq!(results[3], Ok(()));
+        // without a timestamp the age of the dispute is unknown
+        assert_eq!(results[5], Ok(()));
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 10.0);
     }
 
     #[test]
-    fn should_process_withdrawal_chargeback() {
-        let mut stats = HashMap::new();
+    fn should_resolve_dispute_open_past_deadline() {
+        let mut engine = Engine {
+            dispute_windows: DisputeWindows::load(DISPUTE_WINDOWS.as_bytes()).unwrap(),
+            ..Default::default()
+        };
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(10.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(2.0),
+                amount: None,
                 client: 1,
-                tx: 2,
-                r#type: "withdrawal".to_string(),
+                tx: 1,
+                r#type: "dispute".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-02T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
                 amount: None,
                 client: 1,
-                tx: 2,
-                r#type: "dispute".to_string(),
+                tx: 1,
+                r#type: "resolve".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-03T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
                 amount: None,
                 client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-05T10:00:00Z").ok(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
+                client: 2,
                 tx: 2,
-                r#type: "chargeback".to_string(),
+                r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-09T10:00:00Z").ok(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
         for txn in txns {
-            process_txn(txn, records, &mut stats);
+            assert_eq!(engine.apply(txn), Ok(()));
         }
+        // the dispute opened again on the 5th is still open on the 9th
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 10.0);
 
-        assert_eq!(stats.get(&1).unwrap().available, 5.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 5.0);
-        assert!(stats.get(&1).unwrap().locked);
+        engine.expire_disputes(DateTime::parse_from_rfc3339("2022-01-12T10:00:00Z").unwrap());
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 10.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, 0.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).total, 10.0);
     }
 
     #[test]
-    fn should_ignore_resolve_if_no_dispute_deposit_txn() {
-        let mut stats = HashMap::new();
+    fn should_void_authorization_past_lifetime() {
+        let mut engine = Engine {
+            authorization_lifetime: Some(TimeDelta::days(7)),
+            ..Default::default()
+        };
+        engine.check_invariants();
+        let input = "type, client, tx, amount, timestamp
+deposit, 1, 1, 100.0, 2022-01-01T00:00:00Z
+authorize, 1, 2, 30.0, 2022-01-01T10:00:00Z
+authorize, 1, 3, 20.0, 2022-01-03T10:00:00Z
+deposit, 2, 4, 1.0, 2022-01-09T00:00:00Z
+capture, 1, 2,, 2022-01-09T00:00:00Z
+capture, 1, 3,, 2022-01-09T00:00:00Z
+";
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        let mut results = vec![];
+        for txn in rdr.deserialize() {
+            results.push(engine.apply_verified(txn.unwrap()).unwrap());
+            engine.ledger.journal.clear();
+        }
+
+        // the first one ran out on the 8th, before it was captured
+        assert_eq!(results[4], Err(Rejection::UnknownTransaction));
+        assert_eq!(results[5], Ok(()));
+        let balance = engine.stats.get(&1).unwrap().balance(&None);
+        assert_eq!(balance.available, 80.0);
+        assert_eq!(balance.held, 0.0);
+        assert_eq!(balance.total, 80.0);
+        assert!(engine.check_ledger().is_ok());
+    }
+
+    #[test]
+    fn should_void_authorization_at_as_of_time() {
+        let args: Vec<String> = "bulbasaur - --as-of 2022-01-10T00:00:00Z --authorization-days 7"
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        let config = CliConfig::new(&args).unwrap();
+        let input = "type, client, tx, amount, timestamp
+deposit, 1, 1, 100.0, 2022-01-01T00:00:00Z
+authorize, 1, 2, 30.0, 2022-01-01T10:00:00Z
+authorize, 1, 3, 20.0, 2022-01-05T10:00:00Z
+";
+        let accounts = check(&config, input.as_bytes()).unwrap();
+
+        // the one of the 5th still holds its funds on the 10th
+        assert_eq!(accounts[0].available, 80.0);
+        assert_eq!(accounts[0].held, 20.0);
+    }
+
+    #[test]
+    fn should_not_charge_back_expired_dispute() {
+        let mut engine = Engine {
+            dispute_windows: DisputeWindows::load(DISPUTE_WINDOWS.as_bytes()).unwrap(),
+            ..Default::default()
+        };
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(10.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-01T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(3.0),
+                amount: None,
+                client: 1,
+                tx: 1,
+                r#type: "dispute".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-02T10:00:00Z").ok(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(1.0),
                 client: 1,
                 tx: 2,
                 r#type: "deposit".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-09T10:00:00Z").ok(),
+                ..Default::default()
             },
             Transaction {
                 amount: None,
                 client: 1,
-                tx: 2,
-                r#type: "resolve".to_string(),
+                tx: 1,
+                r#type: "chargeback".to_string(),
+                timestamp: DateTime::parse_from_rfc3339("2022-01-10T10:00:00Z").ok(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
-        for txn in txns {
-            process_txn(txn, records, &mut stats);
-        }
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
 
-        assert_eq!(stats.get(&1).unwrap().available, 8.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 8.0);
-        assert!(!stats.get(&1).unwrap().locked);
+        assert_eq!(results[3], Err(Rejection::NotDisputed));
+        let stats = engine.stats.get(&1).unwrap();
+        assert!(!stats.locked);
+        assert_eq!(stats.balance(&None).available, 11.0);
+        assert_eq!(stats.balance(&None).held, 0.0);
     }
 
     #[test]
-    fn should_ignore_resolve_if_no_dispute_withdrawal_txn() {
-        let mut stats = HashMap::new();
+    fn should_keep_balances_in_line_with_ledger() {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let data = "client,currency,available,held,total,locked
+3,USD,1.005,0.005,1.01,false
+";
+        load_opening_balances(data.as_bytes(), &mut engine).unwrap();
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(100.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(3.0),
+                amount: Some(20.0),
                 client: 1,
                 tx: 2,
                 r#type: "withdrawal".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(30.0),
+                client: 1,
+                tx: 3,
+                r#type: "transfer".to_string(),
+                currency: Some("USD".to_string()),
+                destination: Some(2),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(10.0),
+                client: 2,
+                tx: 4,
+                r#type: "exchange".to_string(),
+                currency: Some("USD".to_string()),
+                to_currency: Some("EUR".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(5.0),
+                client: 2,
+                tx: 5,
+                r#type: "authorize".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(2.0),
+                client: 2,
+                tx: 5,
+                r#type: "capture".to_string(),
+                ..Default::default()
             },
             Transaction {
                 amount: None,
                 client: 1,
-                tx: 2,
-                r#type: "resolve".to_string(),
+                tx: 1,
+                r#type: "dispute".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(50.0),
+                client: 1,
+                tx: 1,
+                r#type: "chargeback".to_string(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
         for txn in txns {
-            process_txn(txn, records, &mut stats);
+            assert_eq!(engine.process_txn(txn), Ok(()));
         }
 
-        assert_eq!(stats.get(&1).unwrap().available, 2.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 2.0);
-        assert!(!stats.get(&1).unwrap().locked);
+        assert_eq!(engine.check_ledger(), Ok(()));
+        let usd = Some("USD".to_string());
+        assert_eq!(engine.ledger.balance(Available(1), &usd), -51.7);
+        assert_eq!(engine.ledger.balance(Held(1), &usd), 50.0);
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&usd).total, -1.7);
     }
 
     #[test]
-    fn should_ignore_chargeback_if_no_dispute_deposit_txn() {
-        let mut stats = HashMap::new();
+    fn should_post_each_transaction_in_balance() {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(100.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                currency: Some("USD".to_string()),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(3.0),
-                client: 1,
-                tx: 2,
-                r#type: "deposit".to_string(),
-            },
-            Transaction {
-                amount: None,
+                amount: Some(10.0),
                 client: 1,
                 tx: 2,
-                r#type: "chargeback".to_string(),
+                r#type: "exchange".to_string(),
+                currency: Some("USD".to_string()),
+                to_currency: Some("EUR".to_string()),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
         for txn in txns {
-            process_txn(txn, records, &mut stats);
+            assert_eq!(engine.process_txn(txn), Ok(()));
         }
 
-        assert_eq!(stats.get(&1).unwrap().available, 8.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 8.0);
-        assert!(!stats.get(&1).unwrap().locked);
+        let usd = Some("USD".to_string());
+        let eur = Some("EUR".to_string());
+        let exchange = &engine.ledger.journal[1];
+        assert_eq!(exchange.tx, Some(2));
+        assert_eq!(
+            exchange.entries,
+            vec![
+                Entry::new(Available(1), &usd, -10.0),
+                Entry::new(Exchange, &usd, 10.0),
+                Entry::new(Exchange, &eur, -8.0),
+                Entry::new(Available(1), &eur, 7.92),
+                Entry::new(ExchangeSpread, &eur, 0.08),
+            ]
+        );
+        assert_eq!(engine.ledger.balance(External, &usd), -100.0);
+        assert_eq!(engine.house.exchange_spread[&eur], 0.08);
     }
 
     #[test]
-    fn should_ignore_chargeback_if_no_dispute_withdrawal_txn() {
-        let mut stats = HashMap::new();
+    fn should_replay_history_of_a_client() {
+        let mut engine = Engine::default();
+        let input = "type, client, tx, amount, destination
+deposit, 1, 1, 10.0,
+deposit, 2, 2, 5.0,
+withdrawal, 1, 3, 20.0,
+transfer, 2, 4, 2.0, 1
+dispute, 1, 1,,
+chargeback, 1, 1,,
+";
+        let steps = replay(&mut engine, input.as_bytes(), 1).unwrap();
+
+        let txs: Vec<_> = steps.iter().map(|step| step.tx).collect();
+        assert_eq!(txs, vec![1, 3, 4, 1, 1]);
+        assert_eq!(steps[0].accounts[0].available, 10.0);
+        assert_eq!(steps[1].rejection.as_deref(), Some("insufficient funds"));
+        assert_eq!(steps[1].accounts[0].available, 10.0);
+        assert_eq!(steps[2].accounts[0].available, 12.0);
+        assert_eq!(steps[3].accounts[0].held, 10.0);
+        assert_eq!(steps[3].accounts[0].total, 12.0);
+        assert!(steps[4].accounts[0].locked);
+        assert_eq!(steps[4].accounts[0].total, 2.0);
+    }
+
+    #[test]
+    fn should_replay_steps_before_client_exists() {
+        let mut engine = Engine::default();
+        let input = "type, client, tx, amount
+withdrawal, 3, 1, 1.0
+deposit, 3, 2, 1.0
+";
+        let steps = replay(&mut engine, input.as_bytes(), 3).unwrap();
+
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].rejection.as_deref(), Some("unknown client"));
+        assert!(steps[0].accounts.is_empty());
+        assert_eq!(steps[1].rejection, None);
+        assert_eq!(steps[1].accounts[0].total, 1.0);
+    }
+
+    #[test]
+    fn should_state_period_of_accounts() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            dispute_windows: DisputeWindows::load(DISPUTE_WINDOWS.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let input = "type, client, tx, amount, currency, timestamp
+deposit, 1, 1, 100.0, USD, 2022-01-01T00:00:00Z
+deposit, 1, 2, 50.0, USD, 2022-02-01T00:00:00Z
+dispute, 1, 2,, USD, 2022-02-02T00:00:00Z
+withdrawal, 1, 3, 500.0, USD, 2022-02-03T00:00:00Z
+deposit, 2, 4, 10.0, USD, 2022-02-04T00:00:00Z
+deposit, 1, 5, 10.0, USD, 2022-04-01T00:00:00Z
+";
+        let from = DateTime::parse_from_rfc3339("2022-02-01T00:00:00Z").ok();
+        let to = DateTime::parse_from_rfc3339("2022-04-01T00:00:00Z").ok();
+        let statements = state(&mut engine, input.as_bytes(), from, to).unwrap();
+
+        assert_eq!(statements.len(), 2);
+        let statement = &statements[0];
+        assert_eq!(statement.client, 1);
+        assert_eq!(statement.opening.total, 99.5);
+        let types: Vec<_> = statement
+            .lines
+            .iter()
+            .map(|line| line.r#type.as_str())
+            .collect();
+        // the rejected withdrawal is left out
+        assert_eq!(types, vec!["deposit", "dispute", "expire"]);
+        assert_eq!(statement.lines[0].amount, 50.0);
+        assert_eq!(statement.lines[0].fee, 0.5);
+        assert_eq!(statement.lines[1].balance.held, 50.0);
+        assert_eq!(statement.closing.available, 149.0);
+        assert_eq!(statement.closing.held, 0.0);
+        assert_eq!(statement.fees, 0.5);
+        assert_eq!(statement.disputes_opened, 1);
+        assert_eq!(statement.disputes_closed, 1);
+
+        assert_eq!(statements[1].client, 2);
+        assert_eq!(statements[1].opening.total, 0.0);
+        assert_eq!(statements[1].closing.total, 9.5);
+    }
+
+    #[test]
+    fn should_state_authorizations_that_ran_out() {
+        let mut engine = Engine {
+            authorization_lifetime: Some(TimeDelta::days(7)),
+            as_of: DateTime::parse_from_rfc3339("2022-03-01T00:00:00Z").ok(),
+            ..Default::default()
+        };
+        let input = "type, client, tx, amount, timestamp
+deposit, 1, 1, 100.0, 2022-01-01T00:00:00Z
+authorize, 1, 2, 30.0, 2022-02-01T00:00:00Z
+authorize, 1, 3, 20.0, 2022-02-02T00:00:00Z
+deposit, 1, 4, 10.0, 2022-02-08T12:00:00Z
+";
+        let from = DateTime::parse_from_rfc3339("2022-02-01T00:00:00Z").ok();
+        let statements = state(&mut engine, input.as_bytes(), from, None).unwrap();
+
+        let statement = &statements[0];
+        let lines: Vec<_> = statement
+            .lines
+            .iter()
+            .map(|line| (line.tx, line.r#type.as_str()))
+            .collect();
+        // one runs out with a later row, the other at the as-of time
+        assert_eq!(
+            lines,
+            vec![
+                (Some(2), "authorize"),
+                (Some(3), "authorize"),
+                (Some(2), "void"),
+                (Some(4), "deposit"),
+                (Some(3), "void"),
+            ]
+        );
+        assert_eq!(statement.closing.held, 0.0);
+        assert_eq!(statement.closing.available, 110.0);
+        assert_eq!(statement.disputes_closed, 0);
+    }
+
+    #[test]
+    fn should_hold_invariants_after_every_transaction() {
+        let mut engine = Engine {
+            fees: FeeSchedule::load(FEES.as_bytes()).unwrap(),
+            dispute_windows: DisputeWindows::load(DISPUTE_WINDOWS.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        load_opening_balances(
+            "client,available,held,total,locked\n3,1.0,2.0,3.0,false\n".as_bytes(),
+            &mut engine,
+        )
+        .unwrap();
+        engine.check_invariants();
+        let input = "type, client, tx, amount, destination, timestamp
+deposit, 1, 1, 100.0,, 2022-01-01T00:00:00Z
+withdrawal, 1, 2, 20.0,, 2022-01-02T00:00:00Z
+dispute, 1, 2,,, 2022-01-03T00:00:00Z
+authorize, 1, 3, 30.0,, 2022-01-04T00:00:00Z
+capture, 1, 3, 10.0,, 2022-01-05T00:00:00Z
+transfer, 1, 4, 5.0, 2, 2022-01-06T00:00:00Z
+dispute, 1, 4,,, 2022-01-07T00:00:00Z
+dispute, 1, 1, 10.0,, 2022-01-08T00:00:00Z
+chargeback, 1, 1,,, 2022-01-09T00:00:00Z
+deposit, 2, 5, 1.0,, 2022-01-20T00:00:00Z
+withdrawal, 3, 6, 1.0,, 2022-01-21T00:00:00Z
+";
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        for txn in rdr.deserialize() {
+            let txn: Transaction = txn.unwrap();
+            let watch = engine.watch(&txn).unwrap();
+            let _ = engine.apply(txn);
+            assert_eq!(engine.verify(watch), Ok(()));
+            engine.ledger.journal.clear();
+        }
+
+        // the disputed withdrawal and transfer are held below zero
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).held, -25.0);
+        assert_eq!(engine.stats.get(&3).unwrap().balance(&None).held, 2.0);
+    }
+
+    #[test]
+    fn should_report_broken_invariant() {
+        let mut engine = Engine::default();
+        engine.check_invariants();
+        let deposit = Transaction {
+            amount: Some(10.0),
+            client: 1,
+            tx: 1,
+            r#type: "deposit".to_string(),
+            ..Default::default()
+        };
+        let watch = engine.watch(&deposit).unwrap();
+        engine.apply(deposit).unwrap();
+        assert!(engine.verify(watch).is_ok());
+
+        let withdrawal = Transaction {
+            amount: Some(4.0),
+            client: 1,
+            tx: 2,
+            r#type: "withdrawal".to_string(),
+            ..Default::default()
+        };
+        let watch = engine.watch(&withdrawal).unwrap();
+        engine.apply(withdrawal).unwrap();
+        let stat = engine.stats.get_mut(&1).unwrap();
+        stat.balances.get_mut(&None).unwrap().held = 1.0;
+        let err = engine.verify(watch).unwrap_err();
+
+        assert!(err.starts_with("invariants broken by tx 2 (withdrawal) for client 1"));
+        assert!(err.contains("total in no currency is not available plus held"));
+        assert!(err.contains("held in no currency is not the 0 open disputes"));
+        assert!(err.contains("available 10 -> 6, held 0 -> 1, total 10 -> 6"));
+    }
+
+    #[test]
+    fn should_not_dispute_rejected_withdrawal() {
+        let mut engine = Engine::default();
         let txns = vec![
             Transaction {
-                amount: Some(5.0),
+                amount: Some(1.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(3.0),
+                amount: Some(5.0),
                 client: 1,
                 tx: 2,
                 r#type: "withdrawal".to_string(),
+                ..Default::default()
             },
             Transaction {
                 amount: None,
                 client: 1,
                 tx: 2,
-                r#type: "chargeback".to_string(),
+                r#type: "dispute".to_string(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
-        for txn in txns {
-            process_txn(txn, records, &mut stats);
-        }
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
 
-        assert_eq!(stats.get(&1).unwrap().available, 2.0);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 2.0);
-        assert!(!stats.get(&1).unwrap().locked);
+        assert_eq!(results[1], Err(Rejection::InsufficientFunds));
+        assert_eq!(results[2], Err(Rejection::UnknownTransaction));
+        assert_eq!(engine.stats.get(&1).unwrap().balance(&None).available, 1.0);
     }
 
     #[test]
-    fn should_accept_amount_till_four_digits_for_deposit() {
-        let mut stats = HashMap::new();
+    fn should_reject_amounts_out_of_range() {
+        let mut engine = Engine::default();
         let txns = vec![
             Transaction {
-                amount: Some(5.66666),
+                amount: Some(f32::NAN),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(5.66666),
+                amount: Some(3e38),
                 client: 1,
                 tx: 2,
                 r#type: "deposit".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(f32::NEG_INFINITY),
+                client: 1,
+                tx: 3,
+                r#type: "withdrawal".to_string(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
-        for txn in txns {
-            process_txn(txn, records, &mut stats);
-        }
+        let results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
+
+        assert!(results
+            .iter()
+            .all(|result| *result == Err(Rejection::InvalidAmount)));
+        assert!(!engine.stats.contains_key(&1));
 
-        assert_eq!(stats.get(&1).unwrap().available, 11.3334);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 11.3334);
-        assert!(!stats.get(&1).unwrap().locked);
+        let data = "client,available,held,total,locked\n1,NaN,0,NaN,false\n";
+        assert!(load_opening_balances(data.as_bytes(), &mut Engine::default()).is_err());
     }
 
     #[test]
-    fn should_accept_amount_till_four_digits_for_withdrawal() {
-        let mut stats = HashMap::new();
+    fn should_not_panic_on_amounts_beyond_f32_precision() {
+        let mut engine = Engine::default();
         let txns = vec![
             Transaction {
-                amount: Some(5.66666),
+                amount: Some(1_000_000.0),
                 client: 1,
                 tx: 1,
                 r#type: "deposit".to_string(),
+                ..Default::default()
             },
             Transaction {
-                amount: Some(5.11111),
+                amount: Some(1_000_000.0),
                 client: 1,
                 tx: 2,
-                r#type: "withdrawal".to_string(),
+                r#type: "authorize".to_string(),
+                ..Default::default()
+            },
+            Transaction {
+                amount: Some(0.3),
+                client: 1,
+                tx: 2,
+                r#type: "capture".to_string(),
+                ..Default::default()
             },
         ];
-        let records = &mut vec![];
         for txn in txns {
-            process_txn(txn, records, &mut stats);
+            assert_eq!(engine.process_txn(txn), Ok(()));
         }
+        assert!(engine.check_ledger().is_ok());
 
-        assert_eq!(stats.get(&1).unwrap().available, 0.5556);
-        assert_eq!(stats.get(&1).unwrap().held, 0.0);
-        assert_eq!(stats.get(&1).unwrap().total, 0.5556);
-        assert!(!stats.get(&1).unwrap().locked);
+        // available and held cannot add up to a total f32 keeps
+        let data = "client,available,held,total,locked\n1,1000000,0.3,1000000.3,false\n";
+        let err = load_opening_balances(data.as_bytes(), &mut Engine::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "opening balance for client 1 cannot be kept exactly"
+        );
     }
 }
+
+#[cfg(test)]
+mod properties;
+
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
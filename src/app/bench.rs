@@ -0,0 +1,162 @@
+//! Entry points for the benchmarks of `benches`, which only see the public
+//! items of the crate. Not meant for anything else.
+
+use std::{error::Error, io};
+
+use csv::Trim;
+
+use super::{Engine, Transaction};
+use crate::exchange::RateTable;
+
+/// clients the transactions of a workload are spread over
+const CLIENTS: u16 = 1000;
+
+/// rates of the engines, for exchanges
+const RATES: &str = "from,to,rate,spread,effective
+USD,EUR,0.9,0.01,2022-01-01
+";
+
+/// parse reads every row of `reader` as a transaction and returns how many
+/// there were.
+pub fn parse<R: io::Read>(reader: R) -> Result<usize, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(reader);
+    let mut rows = 0;
+    for transaction in rdr.deserialize() {
+        let _: Transaction = transaction?;
+        rows += 1;
+    }
+    Ok(rows)
+}
+
+/// Workload is a number of transactions of one kind, and the ones that have
+/// to be applied before for them to be accepted.
+pub struct Workload {
+    setup: Vec<Transaction>,
+    txns: Vec<Transaction>,
+}
+
+/// Batch is an engine with the setup of a workload applied, and the
+/// transactions it is still to process.
+pub struct Batch {
+    engine: Engine,
+    txns: Vec<Transaction>,
+}
+
+/// workload returns `count` transactions of `kind`, one of deposit,
+/// withdrawal, transfer, refund, exchange, authorize, capture, void,
+/// dispute, resolve and chargeback.
+pub fn workload(kind: &str, count: u32) -> Workload {
+    let txn = |r#type: &str, tx: u32, amount: Option<f32>| Transaction {
+        r#type: r#type.to_string(),
+        client: (tx % CLIENTS as u32) as u16 + 1,
+        tx,
+        amount,
+        ..Default::default()
+    };
+    // every client has plenty to withdraw or send
+    let funding = (1..=CLIENTS as u32).map(|tx| txn("deposit", tx, Some(1e6)));
+    // deposits to dispute, with ids after the funding ones
+    let deposits = (1..=count).map(|i| txn("deposit", CLIENTS as u32 + i, Some(1.0)));
+    let disputes = (1..=count).map(|i| txn("dispute", CLIENTS as u32 + i, None));
+    let authorizations = (1..=count).map(|i| txn("authorize", CLIENTS as u32 + i, Some(1.0)));
+    let measured = |r#type| (1..=count).map(move |i| txn(r#type, CLIENTS as u32 + i, None));
+
+    let (setup, txns): (Vec<_>, Vec<_>) = match kind {
+        "deposit" => (vec![], measured("deposit").map(with(1.0)).collect()),
+        "withdrawal" => (
+            funding.collect(),
+            measured("withdrawal").map(with(1.0)).collect(),
+        ),
+        "transfer" => (
+            funding.collect(),
+            measured("transfer")
+                .map(with(1.0))
+                .map(|mut txn| {
+                    txn.destination = Some(txn.client % CLIENTS + 1);
+                    txn
+                })
+                .collect(),
+        ),
+        "refund" => (
+            deposits.collect(),
+            // refunds have ids of their own, after the deposits
+            (1..=count)
+                .map(|i| {
+                    let mut refund = txn("refund", CLIENTS as u32 + count + i, Some(1.0));
+                    refund.client = ((CLIENTS as u32 + i) % CLIENTS as u32) as u16 + 1;
+                    refund.reference = Some(CLIENTS as u32 + i);
+                    refund
+                })
+                .collect(),
+        ),
+        "exchange" => (
+            funding.map(in_usd).collect(),
+            measured("exchange")
+                .map(with(1.0))
+                .map(in_usd)
+                .map(|mut txn| {
+                    txn.to_currency = Some("EUR".to_string());
+                    txn
+                })
+                .collect(),
+        ),
+        "authorize" => (
+            funding.collect(),
+            measured("authorize").map(with(1.0)).collect(),
+        ),
+        "capture" | "void" => (
+            funding.chain(authorizations).collect(),
+            measured(kind).collect(),
+        ),
+        "dispute" => (deposits.collect(), disputes.collect()),
+        "resolve" | "chargeback" => (deposits.chain(disputes).collect(), measured(kind).collect()),
+        _ => panic!("no workload of {}", kind),
+    };
+    Workload { setup, txns }
+}
+
+/// in_usd gives a transaction a currency, for it to be exchanged.
+fn in_usd(mut txn: Transaction) -> Transaction {
+    txn.currency = Some("USD".to_string());
+    txn
+}
+
+/// with gives a transaction an amount.
+fn with(amount: f32) -> impl Fn(Transaction) -> Transaction {
+    move |mut txn| {
+        txn.amount = Some(amount);
+        txn
+    }
+}
+
+impl Workload {
+    /// prepare applies the setup to a new engine.
+    pub fn prepare(&self) -> Batch {
+        let mut engine = Engine {
+            rates: RateTable::load(RATES.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        for txn in self.setup.iter().cloned() {
+            engine.process_txn(txn).unwrap();
+        }
+        engine.ledger.journal.clear();
+        Batch {
+            engine,
+            txns: self.txns.clone(),
+        }
+    }
+}
+
+impl Batch {
+    /// process runs every transaction through `process_txn`, and gives the
+    /// batch back for it to be dropped outside of the measure.
+    pub fn process(mut self) -> Self {
+        for txn in self.txns.drain(..) {
+            self.engine.process_txn(txn).unwrap();
+            self.engine.ledger.journal.clear();
+        }
+        self
+    }
+}
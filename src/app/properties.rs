@@ -0,0 +1,321 @@
+//! Properties of the engine checked on random streams of deposits,
+//! withdrawals and disputes. Failing streams are shrunk by proptest to the
+//! fewest transactions that still fail.
+
+use std::collections::HashMap;
+
+use proptest::prelude::*;
+
+use super::{disputable, Engine, Transaction};
+use crate::ledger::LedgerAccount::External;
+
+/// clients the streams are made of, few enough for them to meet
+const CLIENTS: u16 = 3;
+
+/// Op is a transaction of a stream. Disputes name the deposit or withdrawal
+/// they act on by its position among the earlier ones.
+#[derive(Clone, Debug)]
+enum Op {
+    Deposit { client: u16, cents: i64 },
+    Withdrawal { client: u16, cents: i64 },
+    Dispute { target: usize, cents: Option<i64> },
+    Resolve { target: usize, cents: Option<i64> },
+    Chargeback { target: usize, cents: Option<i64> },
+}
+
+/// op makes transactions of `cents`, and disputes of `part` of them.
+fn op(
+    cents: impl Strategy<Value = i64> + Clone,
+    part: impl Strategy<Value = i64> + Clone,
+) -> impl Strategy<Value = Op> {
+    let client = 1..=CLIENTS;
+    let part = proptest::option::of(part);
+    prop_oneof![
+        3 => (client.clone(), cents.clone()).prop_map(|(client, cents)| Op::Deposit { client, cents }),
+        2 => (client, cents).prop_map(|(client, cents)| Op::Withdrawal { client, cents }),
+        2 => (any::<usize>(), part.clone()).prop_map(|(target, cents)| Op::Dispute { target, cents }),
+        1 => (any::<usize>(), part.clone()).prop_map(|(target, cents)| Op::Resolve { target, cents }),
+        1 => (any::<usize>(), part).prop_map(|(target, cents)| Op::Chargeback { target, cents }),
+    ]
+}
+
+/// stream makes amounts small enough for f32 to keep every cent of them and
+/// of their sums, so that the engine can be compared with the model.
+fn stream() -> impl Strategy<Value = Vec<Op>> {
+    proptest::collection::vec(op(1..=1000i64, 1..=500i64), 0..60)
+}
+
+/// wide_stream makes amounts up to 100000 as well, as large as f32 still
+/// keeps cents of, though not the four digits of amounts without currency.
+fn wide_stream() -> impl Strategy<Value = Vec<Op>> {
+    let cents = prop_oneof![3 => 1..=1000i64, 1 => 1..=10_000_000i64];
+    let part = prop_oneof![3 => 1..=500i64, 1 => 1..=5_000_000i64];
+    proptest::collection::vec(op(cents, part), 0..60)
+}
+
+/// transactions turns a stream into rows, every deposit and withdrawal with
+/// a transaction id of its own.
+fn transactions(ops: &[Op]) -> Vec<Transaction> {
+    let mut recorded: Vec<(u32, u16)> = vec![];
+    let mut txns = vec![];
+    for op in ops {
+        let (r#type, client, tx, cents) = match *op {
+            Op::Deposit { client, cents } | Op::Withdrawal { client, cents } => {
+                let tx = recorded.len() as u32 + 1;
+                recorded.push((tx, client));
+                let r#type = match op {
+                    Op::Deposit { .. } => "deposit",
+                    _ => "withdrawal",
+                };
+                (r#type, client, tx, Some(cents))
+            }
+            Op::Dispute { target, cents }
+            | Op::Resolve { target, cents }
+            | Op::Chargeback { target, cents } => {
+                if recorded.is_empty() {
+                    continue;
+                }
+                let (tx, client) = recorded[target % recorded.len()];
+                let r#type = match op {
+                    Op::Dispute { .. } => "dispute",
+                    Op::Resolve { .. } => "resolve",
+                    _ => "chargeback",
+                };
+                (r#type, client, tx, cents)
+            }
+        };
+        txns.push(Transaction {
+            r#type: r#type.to_string(),
+            client,
+            tx,
+            amount: cents.map(|cents| cents as f32 / 100.0),
+            ..Default::default()
+        });
+    }
+    txns
+}
+
+/// cents reads an amount of the engine back in cents.
+fn cents(amount: f32) -> i64 {
+    (amount as f64 * 100.0).round() as i64
+}
+
+/// Account is an account of the reference model, in cents.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Account {
+    available: i64,
+    held: i64,
+    locked: bool,
+}
+
+/// Movement is a recorded deposit, or withdrawal with a negative amount.
+#[derive(Debug)]
+struct Movement {
+    client: u16,
+    amount: i64,
+    disputed: i64,
+    charged_back: i64,
+}
+
+/// Model is the simplest engine that applies the streams.
+#[derive(Default, Debug)]
+struct Model {
+    accounts: HashMap<u16, Account>,
+    movements: HashMap<u32, Movement>,
+}
+
+impl Model {
+    /// apply applies a transaction and tells if it was accepted.
+    fn apply(&mut self, txn: &Transaction) -> bool {
+        let amount = txn.amount.map(cents);
+        match txn.r#type.as_str() {
+            "deposit" => {
+                let amount = amount.unwrap_or(0);
+                // a deposit is taken whatever the balance, even below zero
+                // after a deposit that was spent is disputed, but not once
+                // the account is locked
+                let account = self.accounts.entry(txn.client).or_default();
+                if account.locked {
+                    return false;
+                }
+                account.available += amount;
+                self.movements
+                    .insert(txn.tx, Movement::new(txn.client, amount));
+                true
+            }
+            "withdrawal" => {
+                let amount = amount.unwrap_or(0);
+                match self.accounts.get_mut(&txn.client) {
+                    Some(account) if !account.locked && account.available >= amount => {
+                        account.available -= amount;
+                        self.movements
+                            .insert(txn.tx, Movement::new(txn.client, -amount));
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            kind => {
+                let movement = match self.movements.get_mut(&txn.tx) {
+                    Some(movement) if movement.client == txn.client => movement,
+                    _ => return false,
+                };
+                let (open, remaining) = (
+                    movement.disputed,
+                    movement.amount.abs() - movement.disputed - movement.charged_back,
+                );
+                let most = match kind {
+                    "dispute" => remaining,
+                    _ => open,
+                };
+                let part = amount.unwrap_or(most);
+                if part <= 0 || part > most {
+                    return false;
+                }
+                let signed = part * movement.amount.signum();
+                let account = self.accounts.get_mut(&movement.client).unwrap();
+                match kind {
+                    "dispute" => {
+                        movement.disputed += part;
+                        account.available -= signed;
+                        account.held += signed;
+                    }
+                    "resolve" => {
+                        movement.disputed -= part;
+                        account.available += signed;
+                        account.held -= signed;
+                    }
+                    _ => {
+                        movement.disputed -= part;
+                        movement.charged_back += part;
+                        account.held -= signed;
+                        account.locked = true;
+                    }
+                }
+                true
+            }
+        }
+    }
+}
+
+impl Movement {
+    fn new(client: u16, amount: i64) -> Self {
+        Movement {
+            client,
+            amount,
+            disputed: 0,
+            charged_back: 0,
+        }
+    }
+}
+
+/// account reads the account of a client off the engine, in cents.
+fn account(engine: &Engine, client: u16) -> Option<Account> {
+    let stat = engine.stats.get(&client)?;
+    let balance = stat.balance(&None);
+    Some(Account {
+        available: cents(balance.available),
+        held: cents(balance.held),
+        locked: stat.locked,
+    })
+}
+
+proptest! {
+    #[test]
+    fn should_match_reference_model(ops in stream()) {
+        let mut engine = Engine::default();
+        let mut model = Model::default();
+        for txn in transactions(&ops) {
+            let expected = model.apply(&txn);
+            let tx = txn.tx;
+            prop_assert_eq!(engine.apply(txn).is_ok(), expected, "tx {}", tx);
+        }
+        for client in 1..=CLIENTS {
+            prop_assert_eq!(account(&engine, client), model.accounts.get(&client).copied());
+        }
+    }
+
+    #[test]
+    fn should_conserve_money(ops in wide_stream()) {
+        let mut engine = Engine::default();
+        for txn in transactions(&ops) {
+            let _ = engine.apply(txn);
+            // what clients have is what came in from outside, no more no less
+            // than f32 loses on amounts this large
+            let totals: Vec<f32> = engine
+                .stats
+                .values()
+                .map(|stat| stat.balance(&None).total)
+                .collect();
+            let external = engine.ledger.balance(External, &None);
+            let slack: i64 = totals
+                .iter()
+                .chain([external].iter())
+                .map(|total| cents(total.abs() * f32::EPSILON).max(1))
+                .sum();
+            let sum: i64 = totals.iter().copied().map(cents).sum();
+            prop_assert!((sum + cents(external)).abs() <= slack);
+            prop_assert!(engine.ledger.check().is_ok());
+            prop_assert!(engine.check_ledger().is_ok());
+        }
+    }
+
+    #[test]
+    fn should_only_hold_negative_for_disputed_withdrawals(ops in wide_stream()) {
+        let mut engine = Engine::default();
+        for txn in transactions(&ops) {
+            let _ = engine.apply(txn);
+            for stat in engine.stats.values() {
+                let disputed_withdrawal = engine.records.iter().any(|record| {
+                    record.txn.client == stat.client
+                        && record.txn.r#type == "withdrawal"
+                        && record.disputed > 0.0
+                });
+                // amounts this large keep cents, not four digits
+                prop_assert!(cents(stat.balance(&None).held) >= 0 || disputed_withdrawal);
+            }
+        }
+    }
+
+    #[test]
+    fn should_lock_on_chargeback(ops in wide_stream()) {
+        let mut engine = Engine::default();
+        let mut charged_back = vec![];
+        for txn in transactions(&ops) {
+            let (kind, client) = (txn.r#type.clone(), txn.client);
+            if engine.apply(txn).is_ok() && kind == "chargeback" {
+                charged_back.push(client);
+            }
+            // nothing in the streams unlocks a client
+            for client in charged_back.iter() {
+                prop_assert!(engine.stats[client].locked);
+            }
+        }
+    }
+
+    #[test]
+    fn should_restore_balances_on_resolve(ops in stream(), target in any::<usize>()) {
+        let txns = transactions(&ops);
+        let mut engine = Engine::default();
+        for txn in txns.iter().cloned() {
+            let _ = engine.apply(txn);
+        }
+        prop_assume!(!engine.records.is_empty());
+        let record = &engine.records[target % engine.records.len()];
+        let (tx, client) = (record.txn.tx, record.txn.client);
+        // the resolve must not release an earlier dispute as well
+        let amount = Some(disputable(record));
+        let before = account(&engine, client);
+
+        let row = |r#type: &str| Transaction {
+            r#type: r#type.to_string(),
+            client,
+            tx,
+            amount,
+            ..Default::default()
+        };
+        prop_assume!(engine.apply(row("dispute")).is_ok());
+        prop_assert!(engine.apply(row("resolve")).is_ok());
+        prop_assert_eq!(account(&engine, client), before);
+    }
+}
@@ -1,27 +1,392 @@
+/// Command is what the binary was asked to do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// apply the transactions and print the accounts
+    Run,
+    /// list every step that concerned a client
+    Inspect { client: u16 },
+    /// write the statements of the accounts over a period, of one client only
+    /// when given
+    Statement { client: Option<u16> },
+    /// write made up transactions to the file rather than reading it
+    Generate,
+    /// compare the accounts of the file with those of `theirs`
+    Reconcile,
+}
+
 pub struct CliConfig {
+    pub command: Command,
     pub filename: String,
+    pub opening_balances: Option<String>,
+    pub rates: Option<String>,
+    pub rates_date: Option<String>,
+    pub exchange_rounding: Option<String>,
+    pub house_accounts: Option<String>,
+    pub journal: Option<String>,
+    pub fees: Option<String>,
+    pub clients: Option<String>,
+    pub reservation: Option<String>,
+    pub rules: Option<String>,
+    pub rejections: Option<String>,
+    pub as_of: Option<String>,
+    pub out_of_order: Option<String>,
+    pub dispute_windows: Option<String>,
+    pub authorization_days: Option<String>,
+    pub operators: Option<String>,
+    pub heuristics: Option<String>,
+    pub flags: Option<String>,
+    pub audit: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub format: Option<String>,
+    /// verify the accounting invariants after every transaction
+    pub check: bool,
+    pub expected: Option<String>,
+    pub rows: Option<String>,
+    pub seed: Option<String>,
+    pub client_count: Option<String>,
+    pub mix: Option<String>,
+    pub dispute_rate: Option<String>,
+    pub chargeback_rate: Option<String>,
+    pub malformed_rate: Option<String>,
+    /// the accounts the file is reconciled against
+    pub theirs: Option<String>,
+    pub tolerance: Option<String>,
+    pub relative_tolerance: Option<String>,
+    pub fields: Option<String>,
 }
 
-#[cfg(not(target_os = "windows"))]
-const USAGE: &str = "
+macro_rules! usage {
+    ($bin:literal) => {
+        concat!(
+            "
 Usage:
-    bulbasaur csv_filename.csv
-";
+    ",
+            $bin,
+            " csv_filename.csv [options]
+    ",
+            $bin,
+            " inspect csv_filename.csv --client N [options]
+    ",
+            $bin,
+            " statement csv_filename.csv [--from TIMESTAMP] [--to TIMESTAMP] [options]
+    ",
+            $bin,
+            " generate csv_filename.csv [generate options]
+    ",
+            $bin,
+            " reconcile ours.csv theirs.csv [reconcile options]
+
+Options:
+    --client N                         client to inspect or write the statement of
+    --from TIMESTAMP                   RFC 3339 time the statement period starts at
+    --to TIMESTAMP                     RFC 3339 time the statement period ends before
+    --format csv|text                  how statements are written
+    --opening-balances balances.csv    start from the balances of a previous run
+    --rates rates.csv                  exchange rates for exchange transactions
+    --rates-date YYYY-MM-DD            date exchange rates are looked up for
+    --exchange-rounding nearest|down|up
+                                       rounding of exchanged amounts
+    --fees fees.csv                    fee schedule charged on transactions
+    --clients clients.csv              per client configuration such as the fee tier
+    --reservation available|strict|total
+                                       how held funds count towards spendable funds
+    --rules rules.csv                  risk rules checked before transactions apply
+    --as-of TIMESTAMP                  RFC 3339 time later transactions are not applied after
+    --out-of-order accept|reject       what happens to transactions older than the client's latest
+    --dispute-windows windows.csv      how long transactions can be disputed and disputes stay open
+    --authorization-days N             days an authorization holds its funds before it is voided
+    --operators operators.csv          operators allowed to send admin transactions
+    --heuristics heuristics.csv        flag or lock clients with suspicious disputes
+
+Run options:
+    --house-accounts house.csv         write the house accounts to a file
+    --journal journal.csv              write every ledger entry to a file
+    --rejections rejections.csv        write the rejected transactions to a file
+    --flags flags.csv                  write why clients were flagged to a file
+    --audit audit.csv                  write who sent each admin transaction and why to a file
+    --check                            verify the accounting invariants after every transaction
+
+Generate options:
+    --expected expected.csv            write the balances the transactions should end with
+    --rows N                           number of rows, 1000 by default
+    --seed N                           the same seed always gives the same rows
+    --client-count N                   number of clients, 100 by default
+    --mix deposit=50,withdrawal=30,transfer=20
+                                       weights of the transaction types
+    --dispute-rate 0.01                share of rows that dispute a deposit
+    --chargeback-rate 0.2              share of disputes that are charged back, the others are resolved
+    --malformed-rate 0                 share of rows with garbled values the engine must reject
+
+Reconcile options:
+    --tolerance AMOUNT                 largest difference of an amount that is not a break
+    --relative-tolerance RATIO         largest difference of an amount relative to the larger side
+    --fields available,held,total,locked
+                                       fields compared, all of them by default
+"
+        )
+    };
+}
+
+#[cfg(not(target_os = "windows"))]
+const USAGE: &str = usage!("bulbasaur");
 
 #[cfg(target_os = "windows")]
-const USAGE: &str = "
-Usage:
-    bulbasaur.exe csv_filename.csv
-";
+const USAGE: &str = usage!("bulbasaur.exe");
 
 impl CliConfig {
     pub fn new(args: &[String]) -> Result<Self, &'static str> {
-        if args.len() < 2 {
+        // the other commands take the same arguments, after their name
+        let (name, args) = match args.get(1).map(String::as_str) {
+            Some(name @ ("inspect" | "statement" | "generate" | "reconcile")) => (name, &args[1..]),
+            _ => ("run", args),
+        };
+        let files = if name == "reconcile" { 2 } else { 1 };
+        if args.len() <= files {
             return Err(USAGE);
         }
 
         let filename = args[1].clone();
+        let theirs = (name == "reconcile").then(|| args[2].clone());
+        let mut client = None;
+        let mut opening_balances = None;
+        let mut rates = None;
+        let mut rates_date = None;
+        let mut exchange_rounding = None;
+        let mut house_accounts = None;
+        let mut journal = None;
+        let mut fees = None;
+        let mut clients = None;
+        let mut reservation = None;
+        let mut rules = None;
+        let mut rejections = None;
+        let mut as_of = None;
+        let mut out_of_order = None;
+        let mut dispute_windows = None;
+        let mut authorization_days = None;
+        let mut operators = None;
+        let mut heuristics = None;
+        let mut flags = None;
+        let mut audit = None;
+        let mut from = None;
+        let mut to = None;
+        let mut format = None;
+        let mut check = false;
+        let mut expected = None;
+        let mut rows = None;
+        let mut seed = None;
+        let mut client_count = None;
+        let mut mix = None;
+        let mut dispute_rate = None;
+        let mut chargeback_rate = None;
+        let mut malformed_rate = None;
+        let mut tolerance = None;
+        let mut relative_tolerance = None;
+        let mut fields = None;
+
+        let mut rest = args[1 + files..].iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--client" => {
+                    client = Some(rest.next().ok_or(USAGE)?.parse().map_err(|_| USAGE)?);
+                }
+                "--opening-balances" => {
+                    opening_balances = Some(rest.next().ok_or(USAGE)?.clone());
+                }
+                "--rates" => rates = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--rates-date" => rates_date = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--exchange-rounding" => {
+                    exchange_rounding = Some(rest.next().ok_or(USAGE)?.clone());
+                }
+                "--house-accounts" => house_accounts = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--journal" => journal = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--fees" => fees = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--clients" => clients = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--reservation" => reservation = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--rules" => rules = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--rejections" => rejections = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--as-of" => as_of = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--out-of-order" => out_of_order = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--dispute-windows" => {
+                    dispute_windows = Some(rest.next().ok_or(USAGE)?.clone());
+                }
+                "--authorization-days" => {
+                    authorization_days = Some(rest.next().ok_or(USAGE)?.clone());
+                }
+                "--operators" => operators = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--heuristics" => heuristics = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--flags" => flags = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--audit" => audit = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--from" => from = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--to" => to = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--format" => format = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--check" => check = true,
+                "--expected" => expected = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--rows" => rows = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--seed" => seed = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--client-count" => client_count = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--mix" => mix = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--dispute-rate" => dispute_rate = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--chargeback-rate" => chargeback_rate = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--malformed-rate" => malformed_rate = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--tolerance" => tolerance = Some(rest.next().ok_or(USAGE)?.clone()),
+                "--relative-tolerance" => {
+                    relative_tolerance = Some(rest.next().ok_or(USAGE)?.clone())
+                }
+                "--fields" => fields = Some(rest.next().ok_or(USAGE)?.clone()),
+                _ => return Err(USAGE),
+            }
+        }
+
+        let given = |options: &[&Option<String>]| options.iter().any(|option| option.is_some());
+        // options of the engine, for the commands applying transactions
+        let engine = given(&[
+            &opening_balances,
+            &rates,
+            &rates_date,
+            &exchange_rounding,
+            &fees,
+            &clients,
+            &reservation,
+            &rules,
+            &as_of,
+            &out_of_order,
+            &dispute_windows,
+            &authorization_days,
+            &operators,
+            &heuristics,
+        ]);
+        let period = given(&[&from, &to, &format]);
+        // outputs of a run, inspect and statement do not write them
+        let outputs = given(&[&house_accounts, &journal, &rejections, &flags, &audit]) || check;
+        let generating = given(&[
+            &expected,
+            &rows,
+            &seed,
+            &client_count,
+            &mix,
+            &dispute_rate,
+            &chargeback_rate,
+            &malformed_rate,
+        ]);
+        let reconciling = given(&[&tolerance, &relative_tolerance, &fields]);
+        let replaying = !generating && !reconciling;
+        let command = match (name, client) {
+            ("inspect", Some(client)) if replaying && !period && !outputs => {
+                Command::Inspect { client }
+            }
+            ("statement", client) if replaying && !outputs => Command::Statement { client },
+            ("run", None) if replaying && !period => Command::Run,
+            ("generate", None) if !engine && !period && !outputs && !reconciling => {
+                Command::Generate
+            }
+            ("reconcile", None) if !engine && !period && !outputs && !generating => {
+                Command::Reconcile
+            }
+            _ => return Err(USAGE),
+        };
+
+        Ok(CliConfig {
+            command,
+            filename,
+            opening_balances,
+            rates,
+            rates_date,
+            exchange_rounding,
+            house_accounts,
+            journal,
+            fees,
+            clients,
+            reservation,
+            rules,
+            rejections,
+            as_of,
+            out_of_order,
+            dispute_windows,
+            authorization_days,
+            operators,
+            heuristics,
+            flags,
+            audit,
+            from,
+            to,
+            format,
+            check,
+            expected,
+            rows,
+            seed,
+            client_count,
+            mix,
+            dispute_rate,
+            chargeback_rate,
+            malformed_rate,
+            theirs,
+            tolerance,
+            relative_tolerance,
+            fields,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CliConfig, Command};
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn should_parse_commands() {
+        let config = CliConfig::new(&args("bulbasaur txns.csv --check")).unwrap();
+        assert_eq!(config.command, Command::Run);
+        assert!(config.check);
+
+        let config = CliConfig::new(&args("bulbasaur inspect txns.csv --client 7")).unwrap();
+        assert_eq!(config.command, Command::Inspect { client: 7 });
+
+        let config = CliConfig::new(&args("bulbasaur statement txns.csv --format text")).unwrap();
+        assert_eq!(config.command, Command::Statement { client: None });
 
-        Ok(CliConfig { filename })
+        let config = CliConfig::new(&args("bulbasaur generate txns.csv --rows 10")).unwrap();
+        assert_eq!(config.command, Command::Generate);
+        assert_eq!(config.rows.as_deref(), Some("10"));
+
+        let config = CliConfig::new(&args("bulbasaur reconcile ours.csv theirs.csv")).unwrap();
+        assert_eq!(config.command, Command::Reconcile);
+        assert_eq!(config.filename, "ours.csv");
+        assert_eq!(config.theirs.as_deref(), Some("theirs.csv"));
+    }
+
+    #[test]
+    fn should_refuse_options_of_other_commands() {
+        for line in [
+            "bulbasaur txns.csv --rows 10",
+            "bulbasaur statement txns.csv --tolerance 0.01",
+            "bulbasaur generate txns.csv --fees fees.csv",
+            "bulbasaur generate txns.csv --check",
+            "bulbasaur reconcile ours.csv theirs.csv --seed 1",
+            "bulbasaur reconcile ours.csv theirs.csv --client 1",
+            "bulbasaur reconcile ours.csv",
+        ] {
+            assert!(CliConfig::new(&args(line)).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn should_refuse_run_options_for_inspect_and_statement() {
+        for option in [
+            "--check",
+            "--journal journal.csv",
+            "--rejections rejections.csv",
+            "--flags flags.csv",
+            "--audit audit.csv",
+            "--house-accounts house.csv",
+        ] {
+            for command in ["inspect txns.csv --client 7", "statement txns.csv"] {
+                let line = format!("bulbasaur {} {}", command, option);
+                assert!(CliConfig::new(&args(&line)).is_err(), "{}", line);
+            }
+        }
     }
 }
@@ -0,0 +1,50 @@
+use std::{collections::HashMap, error::Error, io};
+
+use csv::Trim;
+use serde::Deserialize;
+
+use crate::currency;
+
+/// ClientConfig is a row of the client config file.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ClientConfig {
+    pub client: u16,
+    /// pricing tier of the client in the fee schedule
+    #[serde(default)]
+    pub tier: Option<String>,
+    /// how far the client may overdraw its balance in `currency`
+    #[serde(default)]
+    pub credit_limit: Option<f32>,
+    /// currency of the credit line, the balance without a currency when empty
+    #[serde(default)]
+    pub currency: Option<String>,
+}
+
+/// load_clients reads the client config file, keyed by client.
+pub fn load_clients<R: io::Read>(reader: R) -> Result<HashMap<u16, ClientConfig>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(reader);
+    let mut clients = HashMap::new();
+
+    for client in rdr.deserialize() {
+        let client: ClientConfig = client?;
+        if client.credit_limit.is_some_and(|limit| limit < 0.0) {
+            return Err(format!("negative credit limit for client {}", client.client).into());
+        }
+        if let Some(code) = &client.currency {
+            if currency::minor_units(code).is_none() {
+                return Err(format!(
+                    "config for client {} has unknown currency {}",
+                    client.client, code
+                )
+                .into());
+            }
+        }
+        if clients.contains_key(&client.client) {
+            return Err(format!("duplicate config for client {}", client.client).into());
+        }
+        clients.insert(client.client, client);
+    }
+    Ok(clients)
+}
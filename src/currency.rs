@@ -0,0 +1,48 @@
+/// minor_units returns the number of decimal digits of an ISO 4217 currency,
+/// or `None` when the code is not a known currency.
+pub fn minor_units(code: &str) -> Option<u32> {
+    match code {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX"
+        | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => Some(0),
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => Some(3),
+        "CLF" | "UYW" => Some(4),
+        "AED" | "AFN" | "ALL" | "AMD" | "ANG" | "AOA" | "ARS" | "AUD" | "AWG" | "AZN" | "BAM"
+        | "BBD" | "BDT" | "BGN" | "BMD" | "BND" | "BOB" | "BRL" | "BSD" | "BTN" | "BWP" | "BYN"
+        | "BZD" | "CAD" | "CDF" | "CHF" | "CNY" | "COP" | "CRC" | "CUP" | "CVE" | "CZK" | "DKK"
+        | "DOP" | "DZD" | "EGP" | "ERN" | "ETB" | "EUR" | "FJD" | "FKP" | "GBP" | "GEL" | "GHS"
+        | "GIP" | "GMD" | "GTQ" | "GYD" | "HKD" | "HNL" | "HTG" | "HUF" | "IDR" | "ILS" | "INR"
+        | "IRR" | "JMD" | "KES" | "KGS" | "KHR" | "KPW" | "KYD" | "KZT" | "LAK" | "LBP" | "LKR"
+        | "LRD" | "LSL" | "MAD" | "MDL" | "MGA" | "MKD" | "MMK" | "MNT" | "MOP" | "MRU" | "MUR"
+        | "MVR" | "MWK" | "MXN" | "MYR" | "MZN" | "NAD" | "NGN" | "NIO" | "NOK" | "NPR" | "NZD"
+        | "PAB" | "PEN" | "PGK" | "PHP" | "PKR" | "PLN" | "QAR" | "RON" | "RSD" | "RUB" | "SAR"
+        | "SBD" | "SCR" | "SDG" | "SEK" | "SGD" | "SHP" | "SLE" | "SOS" | "SRD" | "SSP" | "STN"
+        | "SVC" | "SYP" | "SZL" | "THB" | "TJS" | "TMT" | "TOP" | "TRY" | "TTD" | "TWD" | "TZS"
+        | "UAH" | "USD" | "UYU" | "UZS" | "VES" | "WST" | "XCD" | "YER" | "ZAR" | "ZMW" | "ZWG" => {
+            Some(2)
+        }
+        _ => None,
+    }
+}
+
+/// precision returns the number of decimal digits amounts in `currency` are
+/// kept to. Amounts without a currency keep four digits.
+pub fn precision(currency: &Option<String>) -> u32 {
+    match currency {
+        Some(code) => minor_units(code).unwrap_or(4),
+        None => 4,
+    }
+}
+
+/// round keeps `amount` to the precision of `currency`, in f64 for large
+/// amounts not to move to another f32 on the way.
+pub fn round(amount: f32, currency: &Option<String>) -> f32 {
+    let factor = 10_f64.powi(precision(currency) as i32);
+    ((amount as f64 * factor).round() / factor) as f32
+}
+
+/// negligible tells if `delta`, a difference between amounts adding up to
+/// `scale`, is nothing in `currency` or no more than f32 loses on amounts
+/// that large.
+pub fn negligible(delta: f32, scale: f32, currency: &Option<String>) -> bool {
+    round(delta, currency) == 0.0 || delta.abs() <= scale * f32::EPSILON
+}
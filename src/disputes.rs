@@ -0,0 +1,105 @@
+use std::{collections::HashMap, error::Error, io};
+
+use chrono::TimeDelta;
+use csv::Trim;
+use serde::Deserialize;
+
+/// transaction kinds that can be disputed
+const KINDS: [&str; 4] = ["deposit", "withdrawal", "transfer", "authorize"];
+
+/// longest window that can be configured, about a hundred years
+const MAX_DAYS: i64 = 36_500;
+
+/// DisputeWindow is a row of the dispute windows file. A `kind` transaction
+/// can only be disputed up to `max_age_days` after it happened, and its
+/// disputes are resolved on their own `resolve_after_days` after they were
+/// opened unless they are resolved or charged back before.
+#[derive(Deserialize, Clone, Debug)]
+pub struct DisputeWindow {
+    pub kind: String,
+    #[serde(default)]
+    pub max_age_days: Option<i64>,
+    #[serde(default)]
+    pub resolve_after_days: Option<i64>,
+}
+
+#[derive(Default, Debug)]
+pub struct DisputeWindows {
+    windows: HashMap<String, DisputeWindow>,
+}
+
+impl DisputeWindows {
+    pub fn load<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(reader);
+        let mut windows = DisputeWindows::default();
+
+        for window in rdr.deserialize() {
+            let window: DisputeWindow = window?;
+            if !KINDS.contains(&window.kind.as_str()) {
+                return Err(format!("{} transactions cannot be disputed", window.kind).into());
+            }
+            let days = [window.max_age_days, window.resolve_after_days];
+            if days
+                .iter()
+                .flatten()
+                .any(|days| !(0..=MAX_DAYS).contains(days))
+            {
+                return Err(format!("dispute window for {} is out of range", window.kind).into());
+            }
+            if windows.windows.contains_key(&window.kind) {
+                return Err(format!("duplicate dispute window for {}", window.kind).into());
+            }
+            windows.windows.insert(window.kind.clone(), window);
+        }
+        Ok(windows)
+    }
+
+    /// max_age returns how long after it happened a `kind` transaction can be
+    /// disputed, without a limit when `None`.
+    pub fn max_age(&self, kind: &str) -> Option<TimeDelta> {
+        let days = self.windows.get(kind)?.max_age_days?;
+        Some(TimeDelta::days(days))
+    }
+
+    /// resolve_after returns how long a dispute of a `kind` transaction stays
+    /// open, until it is resolved or charged back when `None`.
+    pub fn resolve_after(&self, kind: &str) -> Option<TimeDelta> {
+        let days = self.windows.get(kind)?.resolve_after_days?;
+        Some(TimeDelta::days(days))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::DisputeWindows;
+
+    #[test]
+    fn should_look_up_window_of_kind() {
+        let windows = "kind,max_age_days,resolve_after_days\ndeposit,120,30\nwithdrawal,,7\n";
+        let windows = DisputeWindows::load(windows.as_bytes()).unwrap();
+
+        assert_eq!(windows.max_age("deposit"), Some(TimeDelta::days(120)));
+        assert_eq!(windows.resolve_after("deposit"), Some(TimeDelta::days(30)));
+        assert_eq!(windows.max_age("withdrawal"), None);
+        assert_eq!(
+            windows.resolve_after("withdrawal"),
+            Some(TimeDelta::days(7))
+        );
+        assert_eq!(windows.max_age("transfer"), None);
+    }
+
+    #[test]
+    fn should_reject_invalid_dispute_windows() {
+        let kind = "kind,max_age_days,resolve_after_days\nrefund,1,1\n";
+        let negative = "kind,max_age_days,resolve_after_days\ndeposit,-1,\n";
+        let duplicate = "kind,max_age_days,resolve_after_days\ndeposit,1,\ndeposit,2,\n";
+
+        assert!(DisputeWindows::load(kind.as_bytes()).is_err());
+        assert!(DisputeWindows::load(negative.as_bytes()).is_err());
+        assert!(DisputeWindows::load(duplicate.as_bytes()).is_err());
+    }
+}
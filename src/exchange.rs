@@ -0,0 +1,199 @@
+use std::{collections::HashMap, error::Error, io, str::FromStr};
+
+use chrono::NaiveDate;
+use csv::Trim;
+use serde::Deserialize;
+
+use crate::currency;
+
+/// Rate is a row of the rates file. It converts `from` into `to` starting on
+/// the `effective` date, until a later rate for the same pair takes over.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Rate {
+    pub from: String,
+    pub to: String,
+    /// units of `to` for one unit of `from` at mid-market
+    pub rate: f32,
+    /// share of the converted amount kept by the house, `0.01` for 1%
+    #[serde(default)]
+    pub spread: Option<f32>,
+    pub effective: NaiveDate,
+}
+
+#[derive(Default, Debug)]
+pub struct RateTable {
+    /// rates by currency pair, sorted by effective date
+    rates: HashMap<(String, String), Vec<Rate>>,
+}
+
+impl RateTable {
+    pub fn load<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(reader);
+        let mut table = RateTable::default();
+
+        for rate in rdr.deserialize() {
+            let rate: Rate = rate?;
+            for code in [&rate.from, &rate.to] {
+                if currency::minor_units(code).is_none() {
+                    return Err(format!("rates file has unknown currency {}", code).into());
+                }
+            }
+            if rate.rate <= 0.0 || !(0.0..1.0).contains(&rate.spread.unwrap_or(0.0)) {
+                return Err(format!(
+                    "rate from {} to {} on {} is out of range",
+                    rate.from, rate.to, rate.effective
+                )
+                .into());
+            }
+
+            let rates = table
+                .rates
+                .entry((rate.from.clone(), rate.to.clone()))
+                .or_default();
+            match rates.binary_search_by(|known| known.effective.cmp(&rate.effective)) {
+                Ok(_) => {
+                    return Err(format!(
+                        "duplicate rate from {} to {} on {}",
+                        rate.from, rate.to, rate.effective
+                    )
+                    .into())
+                }
+                Err(pos) => rates.insert(pos, rate),
+            }
+        }
+        Ok(table)
+    }
+
+    /// lookup returns the rate converting `from` into `to` that is in effect
+    /// on `date`, or the latest known rate when there is no date.
+    pub fn lookup(&self, from: &str, to: &str, date: Option<NaiveDate>) -> Option<&Rate> {
+        let rates = self.rates.get(&(from.to_string(), to.to_string()))?;
+        match date {
+            Some(date) => rates.iter().rev().find(|rate| rate.effective <= date),
+            None => rates.last(),
+        }
+    }
+}
+
+/// Rounding decides which way a converted amount that does not fit the
+/// precision of its currency goes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Rounding {
+    #[default]
+    Nearest,
+    Down,
+    Up,
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Rounding::Nearest),
+            "down" => Ok(Rounding::Down),
+            "up" => Ok(Rounding::Up),
+            _ => Err(format!(
+                "unknown rounding {}, expected nearest, down or up",
+                s
+            )),
+        }
+    }
+}
+
+impl Rounding {
+    pub fn apply(self, amount: f32, currency: &Option<String>) -> f32 {
+        let factor = 10_f32.powi(currency::precision(currency) as i32);
+        // the scaled amount is rounded first so representation noise such as
+        // 1.0000001 is not pushed up a whole minor unit
+        let scaled = (amount * factor * 1000.0).round() / 1000.0;
+        let rounded = match self {
+            Rounding::Nearest => scaled.round(),
+            Rounding::Down => scaled.floor(),
+            Rounding::Up => scaled.ceil(),
+        };
+        rounded / factor
+    }
+}
+
+/// Conversion splits an exchanged amount between the client and the house.
+#[derive(Debug, PartialEq)]
+pub struct Conversion {
+    /// amount credited to the client in the target currency
+    pub credited: f32,
+    /// amount kept by the house in the target currency
+    pub spread: f32,
+}
+
+/// convert exchanges `amount` at `rate`. The client is credited the
+/// mid-market value minus the spread, rounded as configured, and whatever is
+/// left of the mid-market value is the house's revenue.
+pub fn convert(amount: f32, rate: &Rate, rounding: Rounding) -> Conversion {
+    let to = Some(rate.to.clone());
+    let value = amount * rate.rate;
+    let credited = rounding.apply(value * (1.0 - rate.spread.unwrap_or(0.0)), &to);
+    let spread = Rounding::Nearest.apply(Rounding::Nearest.apply(value, &to) - credited, &to);
+    Conversion { credited, spread }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::{convert, RateTable, Rounding};
+
+    const RATES: &str = "from,to,rate,spread,effective
+USD,EUR,0.9,0.01,2022-01-01
+USD,EUR,0.8,0.01,2022-02-01
+EUR,USD,1.1,,2022-01-01
+";
+
+    #[test]
+    fn should_lookup_rate_in_effect() {
+        let table = RateTable::load(RATES.as_bytes()).unwrap();
+        let date = |day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok();
+
+        assert_eq!(
+            table.lookup("USD", "EUR", date("2022-01-15")).unwrap().rate,
+            0.9
+        );
+        assert_eq!(
+            table.lookup("USD", "EUR", date("2022-02-01")).unwrap().rate,
+            0.8
+        );
+        assert_eq!(table.lookup("USD", "EUR", None).unwrap().rate, 0.8);
+        assert!(table.lookup("USD", "EUR", date("2021-12-31")).is_none());
+        assert!(table.lookup("EUR", "GBP", None).is_none());
+    }
+
+    #[test]
+    fn should_reject_invalid_rates() {
+        let unknown = "from,to,rate,spread,effective\nUSD,ABC,0.9,0,2022-01-01\n";
+        let negative = "from,to,rate,spread,effective\nUSD,EUR,-0.9,0,2022-01-01\n";
+        let duplicate = "from,to,rate,spread,effective
+USD,EUR,0.9,0,2022-01-01
+USD,EUR,0.8,0,2022-01-01
+";
+        assert!(RateTable::load(unknown.as_bytes()).is_err());
+        assert!(RateTable::load(negative.as_bytes()).is_err());
+        assert!(RateTable::load(duplicate.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn should_split_conversion_between_client_and_house() {
+        let table = RateTable::load(RATES.as_bytes()).unwrap();
+        let rate = table.lookup("USD", "EUR", None).unwrap();
+
+        let nearest = convert(10.01, rate, Rounding::Nearest);
+        assert_eq!(nearest.credited, 7.93);
+        assert_eq!(nearest.spread, 0.08);
+
+        let up = convert(10.01, rate, Rounding::Up);
+        assert_eq!(up.credited, 7.93);
+        let down = convert(10.01, rate, Rounding::Down);
+        assert_eq!(down.credited, 7.92);
+        assert_eq!(down.spread, 0.09);
+    }
+}
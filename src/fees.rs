@@ -0,0 +1,163 @@
+use std::{error::Error, io};
+
+use csv::Trim;
+use serde::Deserialize;
+
+use crate::currency;
+
+/// transaction kinds that can be charged a fee
+const KINDS: [&str; 4] = ["deposit", "withdrawal", "transfer", "exchange"];
+
+/// FeeBand is a row of the fee schedule. It charges `flat` plus `rate` of the
+/// amount, never more than `cap`, on `kind` transactions of at least
+/// `min_amount`. Bands without a `tier` or `currency` apply to every client
+/// tier or currency that has no band of its own.
+#[derive(Deserialize, Clone, Debug)]
+pub struct FeeBand {
+    pub kind: String,
+    #[serde(default)]
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub min_amount: Option<f32>,
+    #[serde(default)]
+    pub flat: Option<f32>,
+    /// share of the amount, `0.01` for 1%
+    #[serde(default)]
+    pub rate: Option<f32>,
+    #[serde(default)]
+    pub cap: Option<f32>,
+}
+
+#[derive(Default, Debug)]
+pub struct FeeSchedule {
+    bands: Vec<FeeBand>,
+}
+
+impl FeeSchedule {
+    pub fn load<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(reader);
+        let mut schedule = FeeSchedule::default();
+
+        for band in rdr.deserialize() {
+            let band: FeeBand = band?;
+            if !KINDS.contains(&band.kind.as_str()) {
+                return Err(format!("fees cannot be charged on {} transactions", band.kind).into());
+            }
+            if let Some(code) = &band.currency {
+                if currency::minor_units(code).is_none() {
+                    return Err(format!("fee schedule has unknown currency {}", code).into());
+                }
+            }
+            let amounts = [band.min_amount, band.flat, band.rate, band.cap];
+            if amounts.iter().flatten().any(|amount| *amount < 0.0) {
+                return Err(format!("fee schedule for {} has a negative amount", band.kind).into());
+            }
+            schedule.bands.push(band);
+        }
+        Ok(schedule)
+    }
+
+    /// fee returns what a `kind` transaction of `amount` costs a client of
+    /// `tier`, before rounding to the precision of `currency`.
+    pub fn fee(&self, kind: &str, tier: Option<&str>, currency: Option<&str>, amount: f32) -> f32 {
+        let band = self
+            .bands
+            .iter()
+            .filter(|band| {
+                band.kind == kind
+                    && (band.tier.is_none() || band.tier.as_deref() == tier)
+                    && (band.currency.is_none() || band.currency.as_deref() == currency)
+                    && amount >= band.min_amount.unwrap_or(0.0)
+            })
+            // a band of the client's own tier and currency wins over the
+            // defaults, then the highest amount band that still applies
+            .max_by(|a, b| {
+                let key = |band: &FeeBand| (band.tier.is_some(), band.currency.is_some());
+                key(a).cmp(&key(b)).then(
+                    a.min_amount
+                        .unwrap_or(0.0)
+                        .total_cmp(&b.min_amount.unwrap_or(0.0)),
+                )
+            });
+
+        match band {
+            Some(band) => {
+                let fee = band.flat.unwrap_or(0.0) + band.rate.unwrap_or(0.0) * amount;
+                match band.cap {
+                    Some(cap) => fee.min(cap),
+                    None => fee,
+                }
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeeSchedule;
+
+    const FEES: &str = "kind,tier,currency,min_amount,flat,rate,cap
+withdrawal,,,,1.0,0.01,5.0
+withdrawal,,,1000,,0.002,
+withdrawal,premium,,,0.0,0.0,
+deposit,,JPY,,100,,
+";
+
+    #[test]
+    fn should_charge_flat_and_percentage_fee() {
+        let schedule = FeeSchedule::load(FEES.as_bytes()).unwrap();
+
+        assert_eq!(schedule.fee("withdrawal", None, None, 100.0), 2.0);
+    }
+
+    #[test]
+    fn should_cap_fee() {
+        let schedule = FeeSchedule::load(FEES.as_bytes()).unwrap();
+
+        assert_eq!(schedule.fee("withdrawal", None, None, 900.0), 5.0);
+    }
+
+    #[test]
+    fn should_charge_fee_of_amount_tier() {
+        let schedule = FeeSchedule::load(FEES.as_bytes()).unwrap();
+
+        assert_eq!(schedule.fee("withdrawal", None, None, 2000.0), 4.0);
+    }
+
+    #[test]
+    fn should_prefer_fee_of_client_tier() {
+        let schedule = FeeSchedule::load(FEES.as_bytes()).unwrap();
+
+        assert_eq!(
+            schedule.fee("withdrawal", Some("premium"), None, 2000.0),
+            0.0
+        );
+        assert_eq!(
+            schedule.fee("withdrawal", Some("standard"), None, 100.0),
+            2.0
+        );
+    }
+
+    #[test]
+    fn should_charge_fee_only_in_its_currency() {
+        let schedule = FeeSchedule::load(FEES.as_bytes()).unwrap();
+
+        assert_eq!(schedule.fee("deposit", None, Some("JPY"), 1000.0), 100.0);
+        assert_eq!(schedule.fee("deposit", None, None, 1000.0), 0.0);
+        assert_eq!(schedule.fee("transfer", None, None, 1000.0), 0.0);
+    }
+
+    #[test]
+    fn should_reject_invalid_fee_schedule() {
+        let kind = "kind,flat\nchargeback,1.0\n";
+        let negative = "kind,flat\ndeposit,-1.0\n";
+
+        assert!(FeeSchedule::load(kind.as_bytes()).is_err());
+        assert!(FeeSchedule::load(negative.as_bytes()).is_err());
+    }
+}
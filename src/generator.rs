@@ -0,0 +1,577 @@
+use std::{cmp::Reverse, collections::BinaryHeap, error::Error, io, str::FromStr};
+
+use serde::Serialize;
+
+use crate::app::Account;
+use crate::cli_config::CliConfig;
+
+/// currency of the rows. Its two digits are kept exact by the f32 amounts of
+/// the engine much further than the four of amounts without a currency.
+const CURRENCY: &str = "USD";
+
+/// balance a client is kept under, in cents
+const CAP: i64 = 100_000;
+
+/// largest amount of a generated transaction, in cents
+const MAX_AMOUNT: i64 = 10_000;
+
+/// deposits remembered to be disputed, a random one is forgotten past it
+const RECENT_DEPOSITS: usize = 10_000;
+
+/// longest a dispute stays open, in rows
+const MAX_DISPUTE_ROWS: u64 = 100;
+
+/// random clients tried for a kind before going through all of them
+const RETRIES: usize = 8;
+
+/// Kind is a transaction type the mix can ask for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Deposit,
+    Withdrawal,
+    Transfer,
+}
+
+/// Mix gives the weight of each transaction type among the rows that are not
+/// disputes or garbled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mix(Vec<(Kind, u32)>);
+
+impl Default for Mix {
+    fn default() -> Self {
+        Mix(vec![
+            (Kind::Deposit, 50),
+            (Kind::Withdrawal, 30),
+            (Kind::Transfer, 20),
+        ])
+    }
+}
+
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = vec![];
+        for part in s.split(',') {
+            let (kind, weight) = part
+                .split_once('=')
+                .ok_or_else(|| format!("mix {} is not kind=weight", part))?;
+            let kind = match kind.trim() {
+                "deposit" => Kind::Deposit,
+                "withdrawal" => Kind::Withdrawal,
+                "transfer" => Kind::Transfer,
+                kind => return Err(format!("unknown kind {} in the mix", kind)),
+            };
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("weight of {:?} is not a number", kind))?;
+            weights.push((kind, weight));
+        }
+        if weights.iter().all(|(_, weight)| *weight == 0) {
+            return Err("mix has no weight".to_string());
+        }
+        Ok(Mix(weights))
+    }
+}
+
+/// Settings is what the rows are generated from.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub rows: u64,
+    pub seed: u64,
+    pub clients: u16,
+    pub mix: Mix,
+    pub dispute_rate: f64,
+    pub chargeback_rate: f64,
+    pub malformed_rate: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            rows: 1000,
+            seed: 0,
+            clients: 100,
+            mix: Mix::default(),
+            dispute_rate: 0.01,
+            chargeback_rate: 0.2,
+            malformed_rate: 0.0,
+        }
+    }
+}
+
+impl Settings {
+    fn new(config: &CliConfig) -> Result<Self, Box<dyn Error>> {
+        let mut settings = Settings::default();
+        if let Some(rows) = &config.rows {
+            settings.rows = rows.parse()?;
+        }
+        if let Some(seed) = &config.seed {
+            settings.seed = seed.parse()?;
+        }
+        if let Some(clients) = &config.client_count {
+            settings.clients = clients.parse()?;
+        }
+        if let Some(mix) = &config.mix {
+            settings.mix = mix.parse()?;
+        }
+        let rate = |rate: &Option<String>, default| match rate {
+            Some(rate) => match rate.parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+                _ => Err(format!("rate {} is not between 0 and 1", rate)),
+            },
+            None => Ok(default),
+        };
+        settings.dispute_rate = rate(&config.dispute_rate, settings.dispute_rate)?;
+        settings.chargeback_rate = rate(&config.chargeback_rate, settings.chargeback_rate)?;
+        settings.malformed_rate = rate(&config.malformed_rate, settings.malformed_rate)?;
+        if settings.clients < 2 {
+            return Err("generate needs at least 2 clients".into());
+        }
+        Ok(settings)
+    }
+}
+
+/// Rng is SplitMix64, small and good enough to make up transactions.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// below returns a number from 0 to `n` excluded.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// chance returns true with probability `p`.
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// Row is a generated transaction.
+#[derive(Serialize, Debug)]
+struct Row {
+    r#type: &'static str,
+    client: u16,
+    tx: u32,
+    amount: Option<f32>,
+    destination: Option<u16>,
+    currency: &'static str,
+}
+
+/// Balance is what a client should have, in cents.
+#[derive(Clone, Copy, Default, Debug)]
+struct Balance {
+    available: i64,
+    held: i64,
+    locked: bool,
+}
+
+/// Dispute is an open dispute, closed once its row comes.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct Dispute {
+    due: u64,
+    tx: u32,
+    client: u16,
+    cents: i64,
+}
+
+/// Generator makes up rows the engine applies in a known way, and keeps the
+/// balances they lead to.
+struct Generator<'a> {
+    settings: &'a Settings,
+    rng: Rng,
+    /// balances by client, from client 1 on, `None` until it gets funds
+    balances: Vec<Option<Balance>>,
+    /// latest transaction id
+    tx: u32,
+    /// recent deposits that can be disputed, with their client and amount
+    deposits: Vec<(u32, u16, i64)>,
+    disputes: BinaryHeap<Reverse<Dispute>>,
+}
+
+impl<'a> Generator<'a> {
+    fn new(settings: &'a Settings) -> Self {
+        Generator {
+            settings,
+            rng: Rng(settings.seed),
+            balances: vec![None; settings.clients as usize],
+            tx: 0,
+            deposits: vec![],
+            disputes: BinaryHeap::new(),
+        }
+    }
+
+    /// row makes up the `n`th row.
+    fn row(&mut self, n: u64) -> Row {
+        if self
+            .disputes
+            .peek()
+            .is_some_and(|dispute| dispute.0.due <= n)
+        {
+            if let Some(Reverse(dispute)) = self.disputes.pop() {
+                return self.close(dispute);
+            }
+        }
+        if self.rng.chance(self.settings.malformed_rate) {
+            return self.malformed();
+        }
+        if self.rng.chance(self.settings.dispute_rate) {
+            if let Some(row) = self.dispute(n) {
+                return row;
+            }
+        }
+        let total: u32 = self.settings.mix.0.iter().map(|(_, weight)| weight).sum();
+        let mut pick = self.rng.below(total as u64) as u32;
+        let mut kind = Kind::Deposit;
+        for (candidate, weight) in self.settings.mix.0.iter() {
+            if pick < *weight {
+                kind = *candidate;
+                break;
+            }
+            pick -= weight;
+        }
+        // a client that is full, empty or locked gives its turn to another
+        for _ in 0..RETRIES {
+            let client = self.client();
+            if let Some(row) = self.act(kind, client) {
+                return row;
+            }
+        }
+        // few clients can do it, any of them does, or something else
+        let start = self.client();
+        for offset in 0..self.settings.clients {
+            let client = (start - 1 + offset) % self.settings.clients + 1;
+            for kind in [kind, Kind::Deposit, Kind::Withdrawal] {
+                if let Some(row) = self.act(kind, client) {
+                    return row;
+                }
+            }
+        }
+        // every client is locked, nothing can be applied any more
+        self.malformed()
+    }
+
+    /// act makes up a `kind` transaction of `client`, `None` when it cannot
+    /// do it.
+    fn act(&mut self, kind: Kind, client: u16) -> Option<Row> {
+        match kind {
+            Kind::Deposit => self.deposit(client),
+            Kind::Withdrawal => self.withdrawal(client),
+            Kind::Transfer => self.transfer(client),
+        }
+    }
+
+    fn client(&mut self) -> u16 {
+        self.rng.below(self.settings.clients as u64) as u16 + 1
+    }
+
+    fn balance(&mut self, client: u16) -> &mut Balance {
+        self.balances[client as usize - 1].get_or_insert_with(Balance::default)
+    }
+
+    fn amount(&mut self, most: i64) -> i64 {
+        self.rng.below(most.min(MAX_AMOUNT) as u64) as i64 + 1
+    }
+
+    fn next_tx(&mut self) -> u32 {
+        self.tx += 1;
+        self.tx
+    }
+
+    fn deposit(&mut self, client: u16) -> Option<Row> {
+        let balance = self.balances[client as usize - 1].unwrap_or_default();
+        let room = CAP - balance.available - balance.held;
+        // the engine refuses deposits of locked accounts
+        if balance.locked || room <= 0 {
+            return None;
+        }
+        let cents = self.amount(room);
+        self.balance(client).available += cents;
+        let tx = self.next_tx();
+        if self.deposits.len() < RECENT_DEPOSITS {
+            self.deposits.push((tx, client, cents));
+        } else {
+            let slot = self.rng.below(RECENT_DEPOSITS as u64) as usize;
+            self.deposits[slot] = (tx, client, cents);
+        }
+        Some(row("deposit", client, tx, cents))
+    }
+
+    fn withdrawal(&mut self, client: u16) -> Option<Row> {
+        let balance = self.balances[client as usize - 1]?;
+        if balance.locked || balance.available <= 0 {
+            return None;
+        }
+        let cents = self.amount(balance.available);
+        self.balance(client).available -= cents;
+        let tx = self.next_tx();
+        Some(row("withdrawal", client, tx, cents))
+    }
+
+    fn transfer(&mut self, client: u16) -> Option<Row> {
+        let from = self.balances[client as usize - 1]?;
+        let mut destination = self.client();
+        if destination == client {
+            destination = destination % self.settings.clients + 1;
+        }
+        let to = self.balances[destination as usize - 1].unwrap_or_default();
+        let room = CAP - to.available - to.held;
+        // locked accounts cannot send nor receive transfers
+        if from.locked || to.locked || from.available <= 0 || room <= 0 {
+            return None;
+        }
+        let cents = self.amount(from.available.min(room));
+        self.balance(client).available -= cents;
+        self.balance(destination).available += cents;
+        let tx = self.next_tx();
+        let mut row = row("transfer", client, tx, cents);
+        row.destination = Some(destination);
+        Some(row)
+    }
+
+    /// dispute disputes a recent deposit, as long as its client still has
+    /// the funds so that available never goes below zero.
+    fn dispute(&mut self, n: u64) -> Option<Row> {
+        if self.deposits.is_empty() {
+            return None;
+        }
+        let pick = self.rng.below(self.deposits.len() as u64) as usize;
+        let (tx, client, cents) = self.deposits[pick];
+        if self.balance(client).available < cents {
+            return None;
+        }
+        self.deposits.swap_remove(pick);
+        let balance = self.balance(client);
+        balance.available -= cents;
+        balance.held += cents;
+        let due = n + 1 + self.rng.below(MAX_DISPUTE_ROWS);
+        self.disputes.push(Reverse(Dispute {
+            due,
+            tx,
+            client,
+            cents,
+        }));
+        Some(Row {
+            r#type: "dispute",
+            client,
+            tx,
+            amount: None,
+            destination: None,
+            currency: CURRENCY,
+        })
+    }
+
+    fn close(&mut self, dispute: Dispute) -> Row {
+        let chargeback = self.rng.chance(self.settings.chargeback_rate);
+        let balance = self.balance(dispute.client);
+        balance.held -= dispute.cents;
+        let r#type = if chargeback {
+            balance.locked = true;
+            "chargeback"
+        } else {
+            balance.available += dispute.cents;
+            "resolve"
+        };
+        Row {
+            r#type,
+            client: dispute.client,
+            tx: dispute.tx,
+            amount: None,
+            destination: None,
+            currency: CURRENCY,
+        }
+    }
+
+    /// malformed makes up a row the engine rejects without touching any
+    /// balance.
+    fn malformed(&mut self) -> Row {
+        let client = self.client();
+        let tx = self.next_tx();
+        let (r#type, tx, amount) = match self.rng.below(4) {
+            0 => ("deposti", tx, Some(1.0)),
+            1 => ("deposit", tx, Some(f32::NAN)),
+            2 => ("withdrawal", tx, None),
+            _ => ("dispute", u32::MAX, None),
+        };
+        Row {
+            r#type,
+            client,
+            tx,
+            amount,
+            destination: None,
+            currency: CURRENCY,
+        }
+    }
+
+    /// expected returns the accounts the rows end with, by client.
+    fn expected(&self) -> Vec<Account> {
+        let amount = |cents: i64| cents as f32 / 100.0;
+        self.balances
+            .iter()
+            .enumerate()
+            .filter_map(|(i, balance)| {
+                let balance = balance.as_ref()?;
+                Some(Account {
+                    client: i as u16 + 1,
+                    currency: Some(CURRENCY.to_string()),
+                    available: amount(balance.available),
+                    held: amount(balance.held),
+                    total: amount(balance.available + balance.held),
+                    locked: balance.locked,
+                    flagged: false,
+                    closed: false,
+                    credit_used: 0.0,
+                })
+            })
+            .collect()
+    }
+}
+
+fn row(r#type: &'static str, client: u16, tx: u32, cents: i64) -> Row {
+    Row {
+        r#type,
+        client,
+        tx,
+        amount: Some(cents as f32 / 100.0),
+        destination: None,
+        currency: CURRENCY,
+    }
+}
+
+/// generate writes the rows of `settings` and returns the accounts an engine
+/// without any configuration ends with after applying them.
+pub fn generate<W: io::Write>(
+    settings: &Settings,
+    writer: W,
+) -> Result<Vec<Account>, Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    let mut generator = Generator::new(settings);
+    for n in 0..settings.rows {
+        wtr.serialize(generator.row(n))?;
+    }
+    wtr.flush()?;
+    Ok(generator.expected())
+}
+
+/// run writes the rows `config` asks for to its file.
+pub fn run(config: &CliConfig) -> Result<(), Box<dyn Error>> {
+    let settings = Settings::new(config)?;
+    let file = std::fs::File::create(&config.filename)?;
+    let expected = generate(&settings, io::BufWriter::new(file))?;
+    if let Some(filename) = &config.expected {
+        let mut wtr = csv::Writer::from_path(filename)?;
+        for account in expected {
+            wtr.serialize(account)?;
+        }
+        wtr.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{app, cli_config::CliConfig};
+
+    use super::{generate, Mix, Settings};
+
+    #[test]
+    fn should_generate_same_rows_for_same_seed() {
+        let settings = Settings {
+            seed: 7,
+            ..Default::default()
+        };
+        let (mut first, mut second) = (vec![], vec![]);
+        generate(&settings, &mut first).unwrap();
+        generate(&settings, &mut second).unwrap();
+        let mut other = vec![];
+        generate(&Settings::default(), &mut other).unwrap();
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        // the header and a row for each
+        assert_eq!(first.iter().filter(|byte| **byte == b'\n').count(), 1001);
+    }
+
+    #[test]
+    fn should_end_with_expected_balances() {
+        let settings = Settings {
+            rows: 1000,
+            seed: 3,
+            clients: 20,
+            dispute_rate: 0.1,
+            chargeback_rate: 0.3,
+            malformed_rate: 0.05,
+            ..Default::default()
+        };
+        let mut rows = vec![];
+        let mut expected = generate(&settings, &mut rows).unwrap();
+
+        let config = CliConfig::new(&["bulbasaur".to_string(), "-".to_string()]).unwrap();
+        let mut accounts = app::check(&config, rows.as_slice()).unwrap();
+        accounts.sort_by_key(|account| account.client);
+        expected.sort_by_key(|account| account.client);
+        assert_eq!(accounts, expected);
+        assert!(accounts.iter().any(|account| account.locked));
+        assert!(accounts.iter().any(|account| account.held > 0.0));
+    }
+
+    #[test]
+    fn should_only_make_applied_rows_without_malformed_rate() {
+        for clients in [100, 10_000] {
+            let settings = Settings {
+                rows: 20_000,
+                clients,
+                ..Default::default()
+            };
+            let mut rows = vec![];
+            generate(&settings, &mut rows).unwrap();
+
+            assert_eq!(app::rejected(rows.as_slice()).unwrap(), vec![]);
+        }
+    }
+
+    #[test]
+    fn should_respect_mix() {
+        let settings = Settings {
+            rows: 20_000,
+            dispute_rate: 0.0,
+            mix: "deposit=50,withdrawal=30,transfer=20".parse().unwrap(),
+            ..Default::default()
+        };
+        let mut rows = vec![];
+        generate(&settings, &mut rows).unwrap();
+
+        let rows = String::from_utf8(rows).unwrap();
+        let share = |kind: &str| {
+            let count = rows.lines().filter(|row| row.starts_with(kind)).count();
+            count as f64 / settings.rows as f64
+        };
+        for (kind, weight) in [("deposit,", 0.5), ("withdrawal,", 0.3), ("transfer,", 0.2)] {
+            assert!(
+                (share(kind) - weight).abs() < 0.02,
+                "{} {}",
+                kind,
+                share(kind)
+            );
+        }
+    }
+
+    #[test]
+    fn should_parse_mix() {
+        let mix: Mix = "deposit=1, transfer=2".parse().unwrap();
+        assert_eq!(mix.0.len(), 2);
+        assert!("deposit=1,refund=1".parse::<Mix>().is_err());
+        assert!("deposit".parse::<Mix>().is_err());
+        assert!("deposit=0".parse::<Mix>().is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    io,
+};
+
+use csv::Trim;
+use serde::{Deserialize, Serialize};
+
+/// heuristics that can be declared in the heuristics file
+const HEURISTICS: [&str; 3] = ["dispute_ratio", "chargebacks", "withdrawal_after_deposit"];
+
+/// Action is what happens to a client a heuristic fires for.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// the client is only marked in the output
+    Flag,
+    /// the client is marked and locked
+    Lock,
+}
+
+/// Heuristic is a row of the heuristics file.
+///
+/// - `dispute_ratio` fires when disputes make up more than `threshold` of a
+///   client's deposits.
+/// - `chargebacks` fires on the `threshold`th chargeback of a client.
+/// - `withdrawal_after_deposit` fires when a deposit is disputed that was
+///   followed right away by a withdrawal of at least `threshold` of it.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Heuristic {
+    pub heuristic: String,
+    pub threshold: f32,
+    pub action: Action,
+}
+
+/// Flag is a row of the flag log, one for each heuristic that fired.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Flag {
+    pub client: u16,
+    /// transaction the heuristic fired on
+    pub tx: u32,
+    pub heuristic: String,
+    pub action: Action,
+}
+
+/// Activity is what the heuristics remember of a client.
+#[derive(Default, Debug)]
+struct Activity {
+    deposits: u32,
+    disputes: u32,
+    chargebacks: u32,
+    /// deposit the latest applied transaction was, with its amount and currency
+    last_deposit: Option<(u32, f32, Option<String>)>,
+    /// deposits withdrawn right after they were made
+    drained: HashSet<u32>,
+}
+
+/// Monitor watches applied transactions for suspicious dispute patterns.
+#[derive(Default, Debug)]
+pub struct Monitor {
+    heuristics: Vec<Heuristic>,
+    activity: HashMap<u16, Activity>,
+    /// heuristics that already fired for a client, they only fire once
+    fired: HashSet<(u16, String)>,
+}
+
+impl Monitor {
+    pub fn load<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(reader);
+        let mut monitor = Monitor::default();
+
+        for heuristic in rdr.deserialize() {
+            let heuristic: Heuristic = heuristic?;
+            if !HEURISTICS.contains(&heuristic.heuristic.as_str()) {
+                return Err(format!("unknown heuristic {}", heuristic.heuristic).into());
+            }
+            if heuristic.threshold <= 0.0 {
+                return Err(format!(
+                    "heuristic {} needs a positive threshold",
+                    heuristic.heuristic
+                )
+                .into());
+            }
+            monitor.heuristics.push(heuristic);
+        }
+        Ok(monitor)
+    }
+
+    /// observe learns about a transaction that was applied and returns the
+    /// heuristics it made fire.
+    pub fn observe(
+        &mut self,
+        kind: &str,
+        client: u16,
+        tx: u32,
+        currency: Option<&str>,
+        amount: Option<f32>,
+    ) -> Vec<Flag> {
+        if self.heuristics.is_empty() {
+            return vec![];
+        }
+        let activity = self.activity.entry(client).or_default();
+        let last_deposit = activity.last_deposit.take();
+        match kind {
+            "deposit" => {
+                activity.deposits += 1;
+                if let Some(amount) = amount {
+                    activity.last_deposit = Some((tx, amount, currency.map(String::from)));
+                }
+            }
+            "withdrawal" => {
+                if let (Some((deposit, deposited, deposit_currency)), Some(amount)) =
+                    (last_deposit, amount)
+                {
+                    if deposit_currency.as_deref() == currency {
+                        let share = amount / deposited;
+                        for heuristic in self.heuristics.iter() {
+                            if heuristic.heuristic == "withdrawal_after_deposit"
+                                && share >= heuristic.threshold
+                            {
+                                activity.drained.insert(deposit);
+                            }
+                        }
+                    }
+                }
+            }
+            "dispute" => activity.disputes += 1,
+            "chargeback" => activity.chargebacks += 1,
+            _ => {}
+        }
+
+        let mut flags = vec![];
+        for heuristic in self.heuristics.iter() {
+            let fires = match (heuristic.heuristic.as_str(), kind) {
+                ("dispute_ratio", "dispute") => {
+                    activity.disputes as f32 > heuristic.threshold * activity.deposits as f32
+                }
+                ("chargebacks", "chargeback") => activity.chargebacks as f32 >= heuristic.threshold,
+                ("withdrawal_after_deposit", "dispute") => activity.drained.contains(&tx),
+                _ => false,
+            };
+            if fires && self.fired.insert((client, heuristic.heuristic.clone())) {
+                flags.push(Flag {
+                    client,
+                    tx,
+                    heuristic: heuristic.heuristic.clone(),
+                    action: heuristic.action,
+                });
+            }
+        }
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Monitor};
+
+    const HEURISTICS: &str = "heuristic,threshold,action
+dispute_ratio,0.5,flag
+chargebacks,2,lock
+withdrawal_after_deposit,0.9,lock
+";
+
+    #[test]
+    fn should_flag_high_dispute_ratio() {
+        let mut monitor = Monitor::load(HEURISTICS.as_bytes()).unwrap();
+        for tx in 1..=4 {
+            monitor.observe("deposit", 1, tx, None, Some(10.0));
+        }
+        assert!(monitor.observe("dispute", 1, 1, None, None).is_empty());
+        assert!(monitor.observe("dispute", 1, 2, None, None).is_empty());
+
+        let flags = monitor.observe("dispute", 1, 3, None, None);
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].heuristic, "dispute_ratio");
+        assert_eq!(flags[0].action, Action::Flag);
+        // a heuristic only fires once for a client
+        assert!(monitor.observe("dispute", 1, 4, None, None).is_empty());
+    }
+
+    #[test]
+    fn should_lock_on_repeated_chargebacks() {
+        let mut monitor = Monitor::load(HEURISTICS.as_bytes()).unwrap();
+        assert!(monitor.observe("chargeback", 1, 1, None, None).is_empty());
+
+        let flags = monitor.observe("chargeback", 1, 2, None, None);
+        assert_eq!(flags[0].heuristic, "chargebacks");
+        assert_eq!(flags[0].action, Action::Lock);
+    }
+
+    #[test]
+    fn should_lock_on_dispute_of_withdrawn_deposit() {
+        let mut monitor = Monitor::load(HEURISTICS.as_bytes()).unwrap();
+        monitor.observe("deposit", 1, 1, Some("USD"), Some(100.0));
+        monitor.observe("withdrawal", 1, 2, Some("USD"), Some(95.0));
+        monitor.observe("deposit", 1, 3, Some("USD"), Some(100.0));
+        monitor.observe("deposit", 1, 4, Some("USD"), Some(100.0));
+        monitor.observe("withdrawal", 1, 5, Some("USD"), Some(50.0));
+
+        assert!(monitor.observe("dispute", 1, 4, None, None).is_empty());
+        let flags = monitor.observe("dispute", 1, 1, None, None);
+        assert!(flags
+            .iter()
+            .any(|flag| flag.heuristic == "withdrawal_after_deposit"));
+    }
+
+    #[test]
+    fn should_reject_invalid_heuristics() {
+        let unknown = "heuristic,threshold,action\nvelocity,1,flag\n";
+        let action = "heuristic,threshold,action\nchargebacks,1,ban\n";
+        let threshold = "heuristic,threshold,action\nchargebacks,0,lock\n";
+
+        assert!(Monitor::load(unknown.as_bytes()).is_err());
+        assert!(Monitor::load(action.as_bytes()).is_err());
+        assert!(Monitor::load(threshold.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::currency::{self, round};
+
+/// LedgerAccount is an account of the double-entry ledger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LedgerAccount {
+    /// funds a client can spend
+    Available(u16),
+    /// funds of a client under dispute or authorized for a payment
+    Held(u16),
+    /// fees earned by the house
+    Fees,
+    /// what the house keeps of exchanged amounts
+    ExchangeSpread,
+    /// the house desk converting between currencies
+    Exchange,
+    /// everything outside the engine, such as the banks deposits come from
+    External,
+}
+
+impl LedgerAccount {
+    /// name returns the account in the journal, without the client.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LedgerAccount::Available(_) => "available",
+            LedgerAccount::Held(_) => "held",
+            LedgerAccount::Fees => "fees",
+            LedgerAccount::ExchangeSpread => "exchange_spread",
+            LedgerAccount::Exchange => "exchange",
+            LedgerAccount::External => "external",
+        }
+    }
+
+    /// client returns the client owning the account, `None` for the house.
+    pub fn client(&self) -> Option<u16> {
+        match self {
+            LedgerAccount::Available(client) | LedgerAccount::Held(client) => Some(*client),
+            _ => None,
+        }
+    }
+}
+
+/// Entry moves `amount` into an account, out of it when negative.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub account: LedgerAccount,
+    pub currency: Option<String>,
+    pub amount: f32,
+}
+
+impl Entry {
+    pub fn new(account: LedgerAccount, currency: &Option<String>, amount: f32) -> Self {
+        Entry {
+            account,
+            currency: currency.clone(),
+            amount: round(amount, currency),
+        }
+    }
+}
+
+/// Posting is every entry of a transaction, they sum to zero in each
+/// currency.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Posting {
+    /// transaction posted, `None` for opening balances
+    pub tx: Option<u32>,
+    pub entries: Vec<Entry>,
+}
+
+/// JournalRow is a row of the journal output, one for each entry.
+#[derive(Serialize, Debug)]
+pub struct JournalRow<'a> {
+    pub tx: Option<u32>,
+    pub account: &'static str,
+    pub client: Option<u16>,
+    pub currency: &'a Option<String>,
+    pub amount: f32,
+}
+
+/// Ledger keeps the balance of every account and the postings that are not
+/// written out yet. Balances are summed in f64, for the house accounts that
+/// sum every client not to drift from them.
+#[derive(Default, Debug)]
+pub struct Ledger {
+    balances: BTreeMap<(LedgerAccount, Option<String>), f64>,
+    pub journal: Vec<Posting>,
+}
+
+impl Ledger {
+    /// post applies the entries of transaction `tx` to the accounts and
+    /// returns the posting. Amounts rounded one by one do not always cancel
+    /// out in f32, so when they are off by no more than that the last entry
+    /// of the currency is made the exact negation of the others.
+    /// Postings that still do not sum to zero are refused and change nothing.
+    pub fn post(&mut self, tx: Option<u32>, mut entries: Vec<Entry>) -> Result<&Posting, String> {
+        let mut currencies: Vec<Option<String>> =
+            entries.iter().map(|entry| entry.currency.clone()).collect();
+        currencies.sort();
+        currencies.dedup();
+        for currency in currencies.iter() {
+            let positions: Vec<usize> = (0..entries.len())
+                .filter(|i| entries[*i].currency == *currency)
+                .collect();
+            let sum: f64 = positions.iter().map(|i| entries[*i].amount as f64).sum();
+            let largest = positions
+                .iter()
+                .map(|i| entries[*i].amount.abs())
+                .fold(0.0, f32::max);
+            // what f32 loses on amounts this large, entry by entry
+            let slack = positions.len() as f64 * (largest * f32::EPSILON) as f64;
+            let last = positions[positions.len() - 1];
+            if positions.len() > 1 && !is_zero(sum, currency) && sum.abs() <= slack {
+                let others = sum - entries[last].amount as f64;
+                entries[last].amount = -others as f32;
+            }
+            let sum: f64 = positions.iter().map(|i| entries[*i].amount as f64).sum();
+            if !is_zero(sum, currency) {
+                return Err(format!("unbalanced posting of tx {:?}: {:?}", tx, entries));
+            }
+        }
+
+        for entry in entries.iter() {
+            let balance = self
+                .balances
+                .entry((entry.account, entry.currency.clone()))
+                .or_default();
+            // the decimal the f32 stands for, for every account to add the same
+            let amount = round64(entry.amount as f64, &entry.currency);
+            *balance = round64(*balance + amount, &entry.currency);
+        }
+        self.journal.push(Posting { tx, entries });
+        Ok(&self.journal[self.journal.len() - 1])
+    }
+
+    /// balance returns the balance of `account` in `currency`.
+    pub fn balance(&self, account: LedgerAccount, currency: &Option<String>) -> f32 {
+        self.balances
+            .get(&(account, currency.clone()))
+            .map_or(0.0, |balance| *balance as f32)
+    }
+
+    /// check verifies that the accounts sum to zero in every currency.
+    pub fn check(&self) -> Result<(), String> {
+        let mut sums: BTreeMap<&Option<String>, f64> = BTreeMap::new();
+        for ((_, currency), balance) in self.balances.iter() {
+            *sums.entry(currency).or_default() += *balance;
+        }
+        match sums
+            .iter()
+            .find(|(currency, sum)| !is_zero(**sum, currency))
+        {
+            Some((currency, sum)) => Err(format!(
+                "ledger is off by {} in {}",
+                sum,
+                currency
+                    .as_deref()
+                    .unwrap_or("the balance without a currency")
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// is_zero tells if `sum` rounds to nothing in `currency`.
+fn is_zero(sum: f64, currency: &Option<String>) -> bool {
+    round64(sum, currency) == 0.0
+}
+
+/// round64 is `round` for the balances of the ledger.
+fn round64(amount: f64, currency: &Option<String>) -> f64 {
+    let factor = 10_f64.powi(currency::precision(currency) as i32);
+    (amount * factor).round() / factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Ledger, LedgerAccount};
+
+    #[test]
+    fn should_post_balanced_entries() {
+        let mut ledger = Ledger::default();
+        let usd = Some("USD".to_string());
+        ledger
+            .post(
+                Some(1),
+                vec![
+                    Entry::new(LedgerAccount::Available(1), &usd, 10.0),
+                    Entry::new(LedgerAccount::External, &usd, -10.0),
+                ],
+            )
+            .unwrap();
+        ledger
+            .post(
+                Some(2),
+                vec![
+                    Entry::new(LedgerAccount::Available(1), &usd, -2.5),
+                    Entry::new(LedgerAccount::Held(1), &usd, 2.5),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(ledger.balance(LedgerAccount::Available(1), &usd), 7.5);
+        assert_eq!(ledger.balance(LedgerAccount::Held(1), &usd), 2.5);
+        assert_eq!(ledger.balance(LedgerAccount::Held(2), &usd), 0.0);
+        assert_eq!(ledger.journal.len(), 2);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn should_refuse_unbalanced_posting() {
+        let mut ledger = Ledger::default();
+        let usd = Some("USD".to_string());
+        let eur = Some("EUR".to_string());
+        let err = ledger
+            .post(
+                Some(1),
+                vec![
+                    Entry::new(LedgerAccount::Available(1), &usd, 10.0),
+                    Entry::new(LedgerAccount::External, &eur, -10.0),
+                ],
+            )
+            .unwrap_err();
+
+        assert!(err.starts_with("unbalanced posting of tx Some(1)"));
+        assert_eq!(ledger.balance(LedgerAccount::Available(1), &usd), 0.0);
+        assert!(ledger.journal.is_empty());
+    }
+
+    #[test]
+    fn should_balance_amounts_f32_cannot_keep() {
+        let mut ledger = Ledger::default();
+        // 999999.7 is 999999.6875 in f32, the capture of 0.3 takes the rest
+        let posting = ledger
+            .post(
+                Some(2),
+                vec![
+                    Entry::new(LedgerAccount::Held(1), &None, -1000000.0),
+                    Entry::new(LedgerAccount::Available(1), &None, 999999.7),
+                    Entry::new(LedgerAccount::External, &None, 0.3),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(posting.entries[2].amount, 0.3125);
+        assert!(ledger.check().is_ok());
+    }
+
+    #[test]
+    fn should_check_balances_f32_cannot_keep() {
+        let mut ledger = Ledger::default();
+        for (tx, (client, amount)) in [(1, 0.01), (2, 4096.0), (1, 0.01)].into_iter().enumerate() {
+            ledger
+                .post(
+                    Some(tx as u32),
+                    vec![
+                        Entry::new(LedgerAccount::Available(client), &None, amount),
+                        Entry::new(LedgerAccount::External, &None, -amount),
+                    ],
+                )
+                .unwrap();
+        }
+
+        // summed in f32, external would drift from what the clients have
+        assert!(ledger.check().is_ok());
+    }
+}
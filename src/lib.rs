@@ -0,0 +1,21 @@
+//! A toy payments engine.
+//!
+//! `app::run` is what the binary does with a config, `app::inspect` replays
+//! the transactions of one for a single client, `generator::generate`
+//! makes up transactions with the balances they end with and
+//! `reconcile::reconcile` compares two account files.
+
+pub mod app;
+pub mod cli_config;
+mod clients;
+mod currency;
+mod disputes;
+mod exchange;
+mod fees;
+pub mod generator;
+mod heuristics;
+mod ledger;
+mod operators;
+pub mod reconcile;
+mod rules;
+pub mod statements;
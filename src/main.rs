@@ -1,20 +1,22 @@
-use std::env;
-use std::process;
+use std::{env, io, process};
 
-mod app;
-mod cli_config;
-use cli_config::CliConfig;
+use bulbasaur::{app, cli_config::CliConfig};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let config = CliConfig::new(&args).unwrap_or_else(|err| {
-        println!("{}", err);
+        eprintln!("{}", err);
         process::exit(1)
     });
 
-    if let Err(err) = app::run(config) {
-        println!("error while running the application: {}", err);
-        process::exit(1)
+    match app::run(config, io::stdout()) {
+        Ok(true) => {}
+        // breaks of a reconcile exit with another code than errors
+        Ok(false) => process::exit(2),
+        Err(err) => {
+            eprintln!("error while running the application: {}", err);
+            process::exit(1)
+        }
     }
 }
@@ -0,0 +1,29 @@
+use std::{collections::HashSet, error::Error, io};
+
+use csv::Trim;
+use serde::Deserialize;
+
+/// Operator is a row of the operators file.
+#[derive(Deserialize, Clone, Debug)]
+struct Operator {
+    operator: String,
+}
+
+/// load_operators reads the operators allowed to send admin transactions.
+pub fn load_operators<R: io::Read>(reader: R) -> Result<HashSet<String>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(reader);
+    let mut operators = HashSet::new();
+
+    for operator in rdr.deserialize() {
+        let operator: Operator = operator?;
+        if operator.operator.is_empty() {
+            return Err("operators file has an empty operator".into());
+        }
+        if !operators.insert(operator.operator.clone()) {
+            return Err(format!("duplicate operator {}", operator.operator).into());
+        }
+    }
+    Ok(operators)
+}
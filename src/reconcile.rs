@@ -0,0 +1,407 @@
+use std::{collections::BTreeMap, error::Error, io, str::FromStr};
+
+use csv::Trim;
+use serde::{Deserialize, Serialize};
+
+use crate::cli_config::CliConfig;
+use crate::currency::round;
+
+/// Field is a column of the account files that is compared.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+impl Field {
+    const ALL: [Field; 4] = [Field::Available, Field::Held, Field::Total, Field::Locked];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Field::Available => "available",
+            Field::Held => "held",
+            Field::Total => "total",
+            Field::Locked => "locked",
+        }
+    }
+
+    /// amount returns the value of an amount field of `row`, `None` for
+    /// `locked`.
+    fn amount(&self, row: &Row) -> Option<Option<f32>> {
+        match self {
+            Field::Available => Some(row.available),
+            Field::Held => Some(row.held),
+            Field::Total => Some(row.total),
+            Field::Locked => None,
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Field::ALL
+            .into_iter()
+            .find(|field| field.name() == s.trim())
+            .ok_or_else(|| format!("unknown field {} to reconcile", s))
+    }
+}
+
+/// Tolerance is how far apart two amounts can be without a break. Either
+/// bound is enough.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tolerance {
+    pub absolute: f32,
+    /// part of the larger of the two amounts
+    pub relative: f32,
+}
+
+impl Tolerance {
+    fn allows(&self, ours: f32, theirs: f32, currency: &Option<String>) -> bool {
+        // rounded, for 10.01 and 10 to be 0.01 apart
+        let delta = round(ours - theirs, currency).abs();
+        delta <= self.absolute || delta <= self.relative * ours.abs().max(theirs.abs())
+    }
+}
+
+/// Row is an account of either file. Every column but the client is
+/// optional, files of the bank do not have to carry them all.
+#[derive(Deserialize, Debug)]
+struct Row {
+    client: u16,
+    #[serde(default)]
+    currency: Option<String>,
+    #[serde(default)]
+    available: Option<f32>,
+    #[serde(default)]
+    held: Option<f32>,
+    #[serde(default)]
+    total: Option<f32>,
+    #[serde(default)]
+    locked: Option<bool>,
+}
+
+/// Value is what a side has in a field.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Value {
+    Amount(f32),
+    Flag(bool),
+}
+
+/// Line is a line of the report, a break or a total.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Line {
+    pub client: Option<u16>,
+    pub currency: Option<String>,
+    /// `missing_ours` or `missing_theirs` for an account only one file has,
+    /// `mismatch` for a field they disagree on, `total` for the sum of a
+    /// field over every account
+    pub issue: &'static str,
+    pub field: Option<&'static str>,
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+    /// ours minus theirs
+    pub delta: Option<f32>,
+}
+
+/// Reconciliation is the outcome of comparing the two files.
+#[derive(Default, Debug)]
+pub struct Reconciliation {
+    /// differences between the two files
+    pub breaks: Vec<Line>,
+    /// sums of the amount fields by currency, breaks or not
+    pub totals: Vec<Line>,
+}
+
+impl Reconciliation {
+    pub fn is_balanced(&self) -> bool {
+        self.breaks.is_empty()
+    }
+}
+
+type Accounts = BTreeMap<(u16, Option<String>), Row>;
+
+fn read<R: io::Read>(reader: R, name: &str) -> Result<Accounts, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(reader);
+    let mut accounts = Accounts::new();
+    for row in rdr.deserialize() {
+        let row: Row = row?;
+        let client = row.client;
+        if accounts
+            .insert((client, row.currency.clone()), row)
+            .is_some()
+        {
+            return Err(format!("account of client {} appears twice in {}", client, name).into());
+        }
+    }
+    Ok(accounts)
+}
+
+/// reconcile compares the accounts of `ours` and `theirs` by client and
+/// currency on `fields`.
+pub fn reconcile<R: io::Read, S: io::Read>(
+    ours: R,
+    theirs: S,
+    fields: &[Field],
+    tolerance: Tolerance,
+) -> Result<Reconciliation, Box<dyn Error>> {
+    let ours = read(ours, "our file")?;
+    let theirs = read(theirs, "their file")?;
+    let mut reconciliation = Reconciliation::default();
+    let missing = |(client, currency): &(u16, Option<String>), issue| Line {
+        client: Some(*client),
+        currency: currency.clone(),
+        issue,
+        field: None,
+        ours: None,
+        theirs: None,
+        delta: None,
+    };
+
+    let mut keys: Vec<_> = ours.keys().chain(theirs.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let (a, b) = match (ours.get(key), theirs.get(key)) {
+            (Some(a), Some(b)) => (a, b),
+            (Some(_), None) => {
+                reconciliation.breaks.push(missing(key, "missing_theirs"));
+                continue;
+            }
+            _ => {
+                reconciliation.breaks.push(missing(key, "missing_ours"));
+                continue;
+            }
+        };
+        let currency = &key.1;
+        for field in fields.iter() {
+            let (ours, theirs, delta) = match field.amount(a).zip(field.amount(b)) {
+                Some((Some(x), Some(y))) if tolerance.allows(x, y, currency) => continue,
+                // neither file has the column
+                Some((None, None)) => continue,
+                Some((x, y)) => (
+                    x.map(Value::Amount),
+                    y.map(Value::Amount),
+                    x.zip(y).map(|(x, y)| round(x - y, currency)),
+                ),
+                None if a.locked == b.locked => continue,
+                None => (a.locked.map(Value::Flag), b.locked.map(Value::Flag), None),
+            };
+            reconciliation.breaks.push(Line {
+                client: Some(key.0),
+                currency: currency.clone(),
+                issue: "mismatch",
+                field: Some(field.name()),
+                ours,
+                theirs,
+                delta,
+            });
+        }
+    }
+
+    // sums in f64 so that many accounts do not lose cents
+    let mut sums: BTreeMap<(&Option<String>, usize), (f64, f64)> = BTreeMap::new();
+    for (side, accounts) in [&ours, &theirs].into_iter().enumerate() {
+        for ((_, currency), row) in accounts.iter() {
+            for (i, field) in fields.iter().enumerate() {
+                if let Some(Some(amount)) = field.amount(row) {
+                    let sum = sums.entry((currency, i)).or_default();
+                    match side {
+                        0 => sum.0 += amount as f64,
+                        _ => sum.1 += amount as f64,
+                    }
+                }
+            }
+        }
+    }
+    for ((currency, i), (ours, theirs)) in sums {
+        let (ours, theirs) = (round(ours as f32, currency), round(theirs as f32, currency));
+        reconciliation.totals.push(Line {
+            client: None,
+            currency: currency.clone(),
+            issue: "total",
+            field: Some(fields[i].name()),
+            ours: Some(Value::Amount(ours)),
+            theirs: Some(Value::Amount(theirs)),
+            delta: Some(round(ours - theirs, currency)),
+        });
+    }
+    Ok(reconciliation)
+}
+
+/// write writes the breaks and then the totals as CSV.
+pub fn write<W: io::Write>(
+    writer: W,
+    reconciliation: &Reconciliation,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for row in reconciliation
+        .breaks
+        .iter()
+        .chain(reconciliation.totals.iter())
+    {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// run reconciles the files of `config` and writes the report to `writer`,
+/// it tells whether the files agree.
+pub fn run<W: io::Write>(config: &CliConfig, writer: W) -> Result<bool, Box<dyn Error>> {
+    let fields = match &config.fields {
+        Some(fields) => fields
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<Field>, _>>()?,
+        None => Field::ALL.to_vec(),
+    };
+    let amount = |amount: &Option<String>| -> Result<f32, Box<dyn Error>> {
+        match amount {
+            Some(amount) => match amount.parse::<f32>() {
+                Ok(amount) if amount >= 0.0 => Ok(amount),
+                _ => Err(format!("tolerance {} is not a positive number", amount).into()),
+            },
+            None => Ok(0.0),
+        }
+    };
+    let tolerance = Tolerance {
+        absolute: amount(&config.tolerance)?,
+        relative: amount(&config.relative_tolerance)?,
+    };
+    let theirs = config
+        .theirs
+        .as_ref()
+        .ok_or("reconcile needs the file of theirs")?;
+    let reconciliation = reconcile(
+        std::fs::File::open(&config.filename)?,
+        std::fs::File::open(theirs)?,
+        &fields,
+        tolerance,
+    )?;
+    write(writer, &reconciliation)?;
+    Ok(reconciliation.is_balanced())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reconcile, write, Field, Tolerance, Value};
+
+    const OURS: &str = "client,currency,available,held,total,locked,flagged,closed,credit_used
+1,USD,10.0,0.0,10.0,false,false,false,0.0
+2,USD,5.0,1.0,6.0,true,false,false,0.0
+3,,1.2345,0.0,1.2345,false,false,false,0.0
+";
+
+    #[test]
+    fn should_report_missing_and_mismatched_accounts() {
+        // the bank has no held funds nor flags
+        let theirs = "client,currency,available,total,locked
+1,USD,10.0,10.0,false
+2,USD,5.5,6.5,false
+4,USD,3.0,3.0,false
+";
+        let fields = [Field::Available, Field::Total, Field::Locked];
+        let reconciliation = reconcile(
+            OURS.as_bytes(),
+            theirs.as_bytes(),
+            &fields,
+            Tolerance::default(),
+        )
+        .unwrap();
+
+        assert!(!reconciliation.is_balanced());
+        let breaks: Vec<_> = reconciliation
+            .breaks
+            .iter()
+            .map(|line| (line.client, line.issue, line.field, line.delta))
+            .collect();
+        assert_eq!(
+            breaks,
+            vec![
+                (Some(2), "mismatch", Some("available"), Some(-0.5)),
+                (Some(2), "mismatch", Some("total"), Some(-0.5)),
+                (Some(2), "mismatch", Some("locked"), None),
+                (Some(3), "missing_theirs", None, None),
+                (Some(4), "missing_ours", None, None),
+            ]
+        );
+        assert_eq!(reconciliation.breaks[2].ours, Some(Value::Flag(true)));
+
+        let mut output = vec![];
+        write(&mut output, &reconciliation).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("client,currency,issue,field,ours,theirs,delta\n"));
+        assert!(output.contains("\n,USD,total,available,15.0,18.5,-3.5\n"));
+        assert!(output.contains("\n,,total,total,1.2345,0.0,1.2345\n"));
+    }
+
+    #[test]
+    fn should_allow_differences_within_tolerance() {
+        let theirs = "client,currency,available,held,total,locked
+1,USD,10.01,0.0,10.01,false
+2,USD,5.2,1.0,6.2,true
+3,,1.2345,0.0,1.2345,false
+";
+        let fields = [Field::Available, Field::Held, Field::Total];
+        let absolute = Tolerance {
+            absolute: 0.01,
+            relative: 0.0,
+        };
+        let relative = Tolerance {
+            absolute: 0.0,
+            relative: 0.05,
+        };
+        let reconcile =
+            |tolerance| reconcile(OURS.as_bytes(), theirs.as_bytes(), &fields, tolerance).unwrap();
+
+        assert_eq!(reconcile(Tolerance::default()).breaks.len(), 4);
+        // 0.2 is too far for the absolute tolerance only
+        assert_eq!(reconcile(absolute).breaks.len(), 2);
+        assert!(reconcile(relative).is_balanced());
+    }
+
+    #[test]
+    fn should_balance_file_with_itself() {
+        // no file has every column, nor every column on every row
+        let theirs = "client,currency,available,total
+1,USD,10.0,10.0
+2,USD,5.0,
+";
+        for file in [OURS, theirs] {
+            let reconciliation = reconcile(
+                file.as_bytes(),
+                file.as_bytes(),
+                &Field::ALL,
+                Tolerance::default(),
+            )
+            .unwrap();
+            assert!(reconciliation.is_balanced());
+        }
+    }
+
+    #[test]
+    fn should_reject_duplicate_accounts() {
+        let theirs = "client,currency,available
+1,USD,10.0
+1,USD,11.0
+";
+        let err = reconcile(
+            OURS.as_bytes(),
+            theirs.as_bytes(),
+            &[Field::Available],
+            Tolerance::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "account of client 1 appears twice in their file"
+        );
+    }
+}
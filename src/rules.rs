@@ -0,0 +1,266 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    io,
+};
+
+use chrono::NaiveDate;
+use csv::Trim;
+use serde::Deserialize;
+
+use crate::currency;
+
+/// rules that can be declared in the rules file
+const RULES: [&str; 4] = [
+    "max_withdrawal",
+    "max_withdrawal_rolling",
+    "max_withdrawal_daily",
+    "max_disputes",
+];
+
+/// Rule is a row of the rules file.
+///
+/// - `max_withdrawal` caps the amount of a single withdrawal.
+/// - `max_withdrawal_rolling` caps the sum of a client's last `window`
+///   withdrawals, the new one included.
+/// - `max_withdrawal_daily` caps the sum of a client's withdrawals on a day,
+///   only withdrawals with a timestamp count.
+/// - `max_disputes` caps the number of disputes a client can open.
+///
+/// Amount rules without a `currency` apply to every currency, in the currency
+/// of the transaction.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Rule {
+    pub rule: String,
+    pub limit: f32,
+    /// number of transactions a rolling rule looks back over
+    #[serde(default)]
+    pub window: Option<usize>,
+    #[serde(default)]
+    pub currency: Option<String>,
+}
+
+/// RuleSet checks transactions against the rules and keeps the per client
+/// history the rules need.
+#[derive(Default, Debug)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    /// latest withdrawals by client and currency, as many as the longest window
+    withdrawals: HashMap<(u16, Option<String>), VecDeque<f32>>,
+    /// withdrawn sum of the latest day by client and currency
+    daily: HashMap<(u16, Option<String>), (NaiveDate, f32)>,
+    disputes: HashMap<u16, usize>,
+}
+
+impl RuleSet {
+    pub fn load<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(reader);
+        let mut set = RuleSet::default();
+
+        for rule in rdr.deserialize() {
+            let rule: Rule = rule?;
+            if !RULES.contains(&rule.rule.as_str()) {
+                return Err(format!("unknown rule {}", rule.rule).into());
+            }
+            if rule.limit < 0.0 {
+                return Err(format!("rule {} has a negative limit", rule.rule).into());
+            }
+            if rule.rule == "max_withdrawal_rolling" && rule.window.unwrap_or(0) == 0 {
+                return Err(format!("rule {} needs a window", rule.rule).into());
+            }
+            if let Some(code) = &rule.currency {
+                if currency::minor_units(code).is_none() {
+                    return Err(format!("rules file has unknown currency {}", code).into());
+                }
+            }
+            set.rules.push(rule);
+        }
+        Ok(set)
+    }
+
+    /// check returns the first rule a `kind` transaction of `amount` by
+    /// `client` on `date` would break.
+    pub fn check(
+        &self,
+        kind: &str,
+        client: u16,
+        currency: Option<&str>,
+        amount: Option<f32>,
+        date: Option<NaiveDate>,
+    ) -> Result<(), String> {
+        let applies =
+            |rule: &&Rule| rule.currency.is_none() || rule.currency.as_deref() == currency;
+        for rule in self.rules.iter().filter(applies) {
+            let broken = match (rule.rule.as_str(), kind, amount) {
+                ("max_withdrawal", "withdrawal", Some(amount)) => amount > rule.limit,
+                ("max_withdrawal_rolling", "withdrawal", Some(amount)) => {
+                    let window = rule.window.unwrap_or(0);
+                    let previous: f32 = self
+                        .withdrawals
+                        .get(&(client, currency.map(String::from)))
+                        .map_or(0.0, |amounts| amounts.iter().rev().take(window - 1).sum());
+                    previous + amount > rule.limit
+                }
+                ("max_withdrawal_daily", "withdrawal", Some(amount)) => match date {
+                    Some(date) => {
+                        let withdrawn = match self.daily.get(&(client, currency.map(String::from)))
+                        {
+                            Some((day, withdrawn)) if *day == date => *withdrawn,
+                            _ => 0.0,
+                        };
+                        withdrawn + amount > rule.limit
+                    }
+                    None => false,
+                },
+                ("max_disputes", "dispute", _) => {
+                    let disputes = self.disputes.get(&client).copied().unwrap_or(0);
+                    (disputes + 1) as f32 > rule.limit
+                }
+                _ => false,
+            };
+            if broken {
+                return Err(format!("{} {}", rule.rule, rule.limit));
+            }
+        }
+        Ok(())
+    }
+
+    /// record adds a transaction that was applied to the history.
+    pub fn record(
+        &mut self,
+        kind: &str,
+        client: u16,
+        currency: Option<&str>,
+        amount: Option<f32>,
+        date: Option<NaiveDate>,
+    ) {
+        match (kind, amount) {
+            ("withdrawal", Some(amount)) => {
+                if let Some(date) = date {
+                    let daily = self
+                        .daily
+                        .entry((client, currency.map(String::from)))
+                        .or_insert((date, 0.0));
+                    if daily.0 != date {
+                        *daily = (date, 0.0);
+                    }
+                    daily.1 += amount;
+                }
+                let longest = self
+                    .rules
+                    .iter()
+                    .filter_map(|rule| rule.window)
+                    .max()
+                    .unwrap_or(0);
+                if longest == 0 {
+                    return;
+                }
+                let amounts = self
+                    .withdrawals
+                    .entry((client, currency.map(String::from)))
+                    .or_default();
+                amounts.push_back(amount);
+                if amounts.len() > longest {
+                    amounts.pop_front();
+                }
+            }
+            ("dispute", _) => *self.disputes.entry(client).or_default() += 1,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::RuleSet;
+
+    const RULES: &str = "rule,limit,window,currency
+max_withdrawal,100,,
+max_withdrawal,5000,,JPY
+max_withdrawal_rolling,150,3,
+max_disputes,1,,
+";
+
+    #[test]
+    fn should_limit_single_withdrawal() {
+        let rules = RuleSet::load(RULES.as_bytes()).unwrap();
+
+        assert!(rules
+            .check("withdrawal", 1, None, Some(100.0), None)
+            .is_ok());
+        assert!(rules
+            .check("withdrawal", 1, None, Some(100.5), None)
+            .is_err());
+        assert!(rules
+            .check("withdrawal", 1, Some("JPY"), Some(1000.0), None)
+            .is_err());
+        assert!(rules.check("deposit", 1, None, Some(1000.0), None).is_ok());
+    }
+
+    #[test]
+    fn should_limit_withdrawals_in_rolling_window() {
+        let mut rules = RuleSet::load(RULES.as_bytes()).unwrap();
+        for amount in [50.0, 60.0] {
+            assert!(rules
+                .check("withdrawal", 1, None, Some(amount), None)
+                .is_ok());
+            rules.record("withdrawal", 1, None, Some(amount), None);
+        }
+
+        assert!(rules
+            .check("withdrawal", 1, None, Some(41.0), None)
+            .is_err());
+        assert!(rules.check("withdrawal", 1, None, Some(40.0), None).is_ok());
+        assert!(rules
+            .check("withdrawal", 2, None, Some(100.0), None)
+            .is_ok());
+        rules.record("withdrawal", 1, None, Some(40.0), None);
+        // the first withdrawal left the window
+        assert!(rules.check("withdrawal", 1, None, Some(50.0), None).is_ok());
+    }
+
+    #[test]
+    fn should_limit_withdrawals_of_a_day() {
+        let rules = "rule,limit,window,currency\nmax_withdrawal_daily,100,,\n";
+        let mut rules = RuleSet::load(rules.as_bytes()).unwrap();
+        let date = |day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok();
+        rules.record("withdrawal", 1, None, Some(80.0), date("2022-01-01"));
+
+        assert!(rules
+            .check("withdrawal", 1, None, Some(30.0), date("2022-01-01"))
+            .is_err());
+        assert!(rules
+            .check("withdrawal", 1, None, Some(30.0), date("2022-01-02"))
+            .is_ok());
+        assert!(rules.check("withdrawal", 1, None, Some(30.0), None).is_ok());
+        rules.record("withdrawal", 1, None, Some(90.0), date("2022-01-02"));
+        assert!(rules
+            .check("withdrawal", 1, None, Some(20.0), date("2022-01-02"))
+            .is_err());
+    }
+
+    #[test]
+    fn should_limit_disputes() {
+        let mut rules = RuleSet::load(RULES.as_bytes()).unwrap();
+        assert!(rules.check("dispute", 1, None, None, None).is_ok());
+        rules.record("dispute", 1, None, None, None);
+
+        assert!(rules.check("dispute", 1, None, None, None).is_err());
+        assert!(rules.check("dispute", 2, None, None, None).is_ok());
+    }
+
+    #[test]
+    fn should_reject_invalid_rules() {
+        let unknown = "rule,limit,window,currency\nmax_deposit,1,,\n";
+        let window = "rule,limit,window,currency\nmax_withdrawal_rolling,1,,\n";
+        let currency = "rule,limit,window,currency\nmax_withdrawal,1,,ABC\n";
+
+        assert!(RuleSet::load(unknown.as_bytes()).is_err());
+        assert!(RuleSet::load(window.as_bytes()).is_err());
+        assert!(RuleSet::load(currency.as_bytes()).is_err());
+    }
+}
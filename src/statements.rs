@@ -0,0 +1,365 @@
+use std::{collections::BTreeMap, error::Error, io, str::FromStr};
+
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+
+use crate::currency::{self, round};
+use crate::ledger::{LedgerAccount, Posting};
+
+/// Format is how statements are written out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Csv,
+    Text,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "text" => Ok(Format::Text),
+            _ => Err(format!("unknown format {}, expected csv or text", s)),
+        }
+    }
+}
+
+/// Totals is the balance of an account at some point of a statement.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Totals {
+    pub available: f32,
+    pub held: f32,
+    pub total: f32,
+}
+
+/// Line is a transaction on a statement, with the balance it left.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub tx: Option<u32>,
+    /// type of the transaction, `expire` for a dispute that ran out and
+    /// `void` for an authorization that did
+    pub r#type: String,
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    /// what the transaction changed the total by, fee aside
+    pub amount: f32,
+    /// fee charged, negative when given back
+    pub fee: f32,
+    pub balance: Totals,
+}
+
+/// Statement is what happened to the account of a client in a currency over
+/// a period.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub client: u16,
+    pub currency: Option<String>,
+    pub opening: Totals,
+    pub lines: Vec<Line>,
+    pub closing: Totals,
+    pub fees: f32,
+    pub disputes_opened: u32,
+    pub disputes_closed: u32,
+}
+
+/// Statements collects the statements of every account while the period
+/// goes on.
+#[derive(Default, Debug)]
+pub struct Statements {
+    statements: BTreeMap<(u16, Option<String>), Statement>,
+}
+
+impl Statements {
+    /// open starts the statement of an account with its balance at the start
+    /// of the period. Accounts that are not opened start from zero.
+    pub fn open(&mut self, client: u16, currency: &Option<String>, opening: Totals) {
+        self.statement(client, currency).opening = opening;
+        self.statement(client, currency).closing = opening;
+    }
+
+    /// record adds the client entries of a posting made by a `kind`
+    /// transaction of `payer`, the client its fees are charged to.
+    pub fn record(
+        &mut self,
+        kind: &str,
+        timestamp: Option<DateTime<FixedOffset>>,
+        payer: Option<u16>,
+        posting: &Posting,
+    ) {
+        let mut changes: BTreeMap<(u16, &Option<String>), (f32, f32)> = BTreeMap::new();
+        let mut fees: BTreeMap<&Option<String>, f32> = BTreeMap::new();
+        for entry in posting.entries.iter() {
+            match entry.account {
+                LedgerAccount::Available(client) => {
+                    changes.entry((client, &entry.currency)).or_default().0 += entry.amount;
+                }
+                LedgerAccount::Held(client) => {
+                    changes.entry((client, &entry.currency)).or_default().1 += entry.amount;
+                }
+                LedgerAccount::Fees => *fees.entry(&entry.currency).or_default() += entry.amount,
+                _ => {}
+            }
+        }
+
+        for ((client, currency), (available, held)) in changes {
+            let fee = match payer == Some(client) {
+                true => fees.get(currency).copied().unwrap_or(0.0),
+                false => 0.0,
+            };
+            let statement = self.statement(client, currency);
+            let balance = &mut statement.closing;
+            balance.available = round(balance.available + available, currency);
+            balance.held = round(balance.held + held, currency);
+            balance.total = round(balance.total + available + held, currency);
+            statement.fees = round(statement.fees + fee, currency);
+            match kind {
+                "dispute" => statement.disputes_opened += 1,
+                "resolve" | "chargeback" | "expire" => statement.disputes_closed += 1,
+                _ => {}
+            }
+            statement.lines.push(Line {
+                tx: posting.tx,
+                r#type: kind.to_string(),
+                timestamp,
+                amount: round(available + held + fee, currency),
+                fee,
+                balance: statement.closing,
+            });
+        }
+    }
+
+    /// finish returns the statements by client and currency.
+    pub fn finish(self) -> Vec<Statement> {
+        self.statements.into_values().collect()
+    }
+
+    fn statement(&mut self, client: u16, currency: &Option<String>) -> &mut Statement {
+        self.statements
+            .entry((client, currency.clone()))
+            .or_insert_with(|| Statement {
+                client,
+                currency: currency.clone(),
+                opening: Totals::default(),
+                lines: vec![],
+                closing: Totals::default(),
+                fees: 0.0,
+                disputes_opened: 0,
+                disputes_closed: 0,
+            })
+    }
+}
+
+/// StatementRow is a row of the CSV statements, the opening and closing
+/// balances included.
+#[derive(Serialize, Debug)]
+struct StatementRow<'a> {
+    client: u16,
+    currency: &'a Option<String>,
+    r#type: &'a str,
+    tx: Option<u32>,
+    timestamp: Option<DateTime<FixedOffset>>,
+    amount: Option<f32>,
+    fee: Option<f32>,
+    available: f32,
+    held: f32,
+    total: f32,
+}
+
+/// write_csv writes the statements as a single CSV. The closing row gives
+/// the change of the total and the fees over the period.
+pub fn write_csv<W: io::Write>(writer: W, statements: &[Statement]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for statement in statements.iter() {
+        let row = |r#type, tx, timestamp, amount, fee, balance: &Totals| StatementRow {
+            client: statement.client,
+            currency: &statement.currency,
+            r#type,
+            tx,
+            timestamp,
+            amount,
+            fee,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+        };
+        wtr.serialize(row("opening", None, None, None, None, &statement.opening))?;
+        for line in statement.lines.iter() {
+            wtr.serialize(row(
+                &line.r#type,
+                line.tx,
+                line.timestamp,
+                Some(line.amount),
+                Some(line.fee),
+                &line.balance,
+            ))?;
+        }
+        let change = round(
+            statement.closing.total - statement.opening.total,
+            &statement.currency,
+        );
+        wtr.serialize(row(
+            "closing",
+            None,
+            None,
+            Some(change),
+            Some(statement.fees),
+            &statement.closing,
+        ))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// write_text writes the statements for people to read, one after the other.
+pub fn write_text<W: io::Write>(
+    mut writer: W,
+    statements: &[Statement],
+    from: Option<DateTime<FixedOffset>>,
+    to: Option<DateTime<FixedOffset>>,
+) -> Result<(), Box<dyn Error>> {
+    let bound = |time: Option<DateTime<FixedOffset>>, none: &str| match time {
+        Some(time) => time.to_rfc3339(),
+        None => none.to_string(),
+    };
+    for statement in statements.iter() {
+        writeln!(
+            writer,
+            "Statement of client {} in {}",
+            statement.client,
+            statement.currency.as_deref().unwrap_or("no currency")
+        )?;
+        writeln!(
+            writer,
+            "From {} to {}",
+            bound(from, "the start"),
+            bound(to, "the end")
+        )?;
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "{:<26}{:<12}{:>8}{:>12}{:>10}{:>12}{:>12}{:>12}",
+            "date", "type", "tx", "amount", "fee", "available", "held", "total"
+        )?;
+        // amounts show every digit of the currency
+        let digits = currency::precision(&statement.currency) as usize;
+        let amount = |amount: f32| format!("{:.*}", digits, amount);
+        let balance = |totals: &Totals| {
+            format!(
+                "{:>12}{:>12}{:>12}",
+                amount(totals.available),
+                amount(totals.held),
+                amount(totals.total)
+            )
+        };
+        writeln!(
+            writer,
+            "{:<68}{}",
+            "opening balance",
+            balance(&statement.opening)
+        )?;
+        for line in statement.lines.iter() {
+            writeln!(
+                writer,
+                "{:<26}{:<12}{:>8}{:>12}{:>10}{}",
+                line.timestamp
+                    .map_or(String::new(), |time| time.to_rfc3339()),
+                line.r#type,
+                line.tx.map_or(String::new(), |tx| tx.to_string()),
+                amount(line.amount),
+                amount(line.fee),
+                balance(&line.balance)
+            )?;
+        }
+        writeln!(
+            writer,
+            "{:<68}{}",
+            "closing balance",
+            balance(&statement.closing)
+        )?;
+        writeln!(writer)?;
+        writeln!(writer, "Fees: {}", amount(statement.fees))?;
+        writeln!(
+            writer,
+            "Disputes opened: {}, closed: {}",
+            statement.disputes_opened, statement.disputes_closed
+        )?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ledger::{Entry, LedgerAccount, Posting};
+
+    use super::{write_csv, Statements, Totals};
+
+    #[test]
+    fn should_keep_running_balance() {
+        let mut statements = Statements::default();
+        let usd = Some("USD".to_string());
+        let opening = Totals {
+            available: 10.0,
+            held: 0.0,
+            total: 10.0,
+        };
+        statements.open(1, &usd, opening);
+        let deposit = Posting {
+            tx: Some(2),
+            entries: vec![
+                Entry::new(LedgerAccount::Available(1), &usd, 5.0),
+                Entry::new(LedgerAccount::External, &usd, -5.0),
+                Entry::new(LedgerAccount::Available(1), &usd, -0.5),
+                Entry::new(LedgerAccount::Fees, &usd, 0.5),
+            ],
+        };
+        let dispute = Posting {
+            tx: Some(2),
+            entries: vec![
+                Entry::new(LedgerAccount::Available(1), &usd, -5.0),
+                Entry::new(LedgerAccount::Held(1), &usd, 5.0),
+            ],
+        };
+        statements.record("deposit", None, Some(1), &deposit);
+        statements.record("dispute", None, Some(1), &dispute);
+        let statements = statements.finish();
+
+        assert_eq!(statements.len(), 1);
+        let statement = &statements[0];
+        assert_eq!(statement.opening, opening);
+        assert_eq!(statement.lines[0].amount, 5.0);
+        assert_eq!(statement.lines[0].fee, 0.5);
+        assert_eq!(statement.lines[0].balance.total, 14.5);
+        assert_eq!(statement.lines[1].amount, 0.0);
+        assert_eq!(statement.closing.available, 9.5);
+        assert_eq!(statement.closing.held, 5.0);
+        assert_eq!(statement.fees, 0.5);
+        assert_eq!(statement.disputes_opened, 1);
+        assert_eq!(statement.disputes_closed, 0);
+    }
+
+    #[test]
+    fn should_write_statement_csv() {
+        let mut statements = Statements::default();
+        let deposit = Posting {
+            tx: Some(1),
+            entries: vec![
+                Entry::new(LedgerAccount::Available(3), &None, 1.5),
+                Entry::new(LedgerAccount::External, &None, -1.5),
+            ],
+        };
+        statements.record("deposit", None, Some(3), &deposit);
+        let mut output = vec![];
+        write_csv(&mut output, &statements.finish()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,currency,type,tx,timestamp,amount,fee,available,held,total
+3,,opening,,,,,0.0,0.0,0.0
+3,,deposit,1,,1.5,0.0,1.5,0.0,1.5
+3,,closing,,,1.5,0.0,1.5,0.0,1.5
+"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Golden tests running every `testdata/<case>/input.csv` through the binary
+//! and comparing what it prints with `testdata/<case>/expected.csv`. Rows can
+//! come in any order, the header must come first.
+//!
+//! A case can give more options in `testdata/<case>/args`, separated by
+//! whitespace, with paths relative to the case directory. The binary must
+//! exit with the code in `testdata/<case>/status`, 0 without one, and print
+//! `testdata/<case>/expected.stderr` to stderr, nothing without one. Run with
+//! `UPDATE_GOLDEN=1` to write what the binary does as the expected outcome.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+fn cases() -> Vec<PathBuf> {
+    let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+    let mut cases: Vec<PathBuf> = fs::read_dir(testdata)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.join("input.csv").is_file())
+        .collect();
+    cases.sort();
+    cases
+}
+
+/// Outcome is what a run of the binary did.
+#[derive(PartialEq, Debug)]
+struct Outcome {
+    status: i32,
+    stdout: String,
+    stderr: String,
+}
+
+/// run runs the binary on the input of `case`.
+fn run(case: &Path) -> Outcome {
+    let args = match fs::read_to_string(case.join("args")) {
+        Ok(args) => args.split_whitespace().map(String::from).collect(),
+        Err(_) => vec![],
+    };
+    let output = Command::new(env!("CARGO_BIN_EXE_bulbasaur"))
+        .current_dir(case)
+        .arg("input.csv")
+        .args(args)
+        .output()
+        .unwrap();
+    Outcome {
+        status: output.status.code().expect("the binary was killed"),
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: String::from_utf8(output.stderr).unwrap(),
+    }
+}
+
+/// expected reads the outcome `case` expects, the rows of its output sorted.
+fn expected(case: &Path) -> Outcome {
+    let read = |name: &str| fs::read_to_string(case.join(name));
+    let stdout = read("expected.csv").unwrap_or_else(|_| panic!("{:?} has no expected.csv", case));
+    Outcome {
+        status: read("status").map_or(0, |status| status.trim().parse().unwrap()),
+        stdout: sorted(&stdout),
+        stderr: read("expected.stderr").unwrap_or_default(),
+    }
+}
+
+/// update writes `outcome` as what `case` expects.
+fn update(case: &Path, outcome: &Outcome) {
+    fs::write(case.join("expected.csv"), sorted(&outcome.stdout)).unwrap();
+    let optional = [
+        (
+            "status",
+            format!("{}\n", outcome.status),
+            outcome.status != 0,
+        ),
+        (
+            "expected.stderr",
+            outcome.stderr.clone(),
+            !outcome.stderr.is_empty(),
+        ),
+    ];
+    for (name, contents, wanted) in optional {
+        match wanted {
+            true => fs::write(case.join(name), contents).unwrap(),
+            false => {
+                let _ = fs::remove_file(case.join(name));
+            }
+        }
+    }
+}
+
+/// sorted returns an output with the header first and then the sorted rows.
+fn sorted(output: &str) -> String {
+    let mut lines = output.lines();
+    let header = lines.next();
+    let mut rows: Vec<&str> = lines.collect();
+    rows.sort_unstable();
+    header
+        .into_iter()
+        .chain(rows)
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[test]
+fn should_match_golden_files() {
+    let updating = env::var_os("UPDATE_GOLDEN").is_some();
+    let cases = cases();
+    assert!(!cases.is_empty(), "no golden cases in testdata");
+
+    let mut failed = vec![];
+    for case in cases.iter() {
+        let name = case.file_name().unwrap().to_string_lossy();
+        let mut actual = run(case);
+        if updating {
+            update(case, &actual);
+            continue;
+        }
+        actual.stdout = sorted(&actual.stdout);
+        let expected = expected(case);
+        if actual != expected {
+            failed.push(format!(
+                "{}\n--- expected, exit {}\n{}{}--- actual, exit {}\n{}{}",
+                name,
+                expected.status,
+                expected.stdout,
+                expected.stderr,
+                actual.status,
+                actual.stdout,
+                actual.stderr
+            ));
+        }
+    }
+    assert!(
+        failed.is_empty(),
+        "golden files do not match, UPDATE_GOLDEN=1 rewrites them:\n{}",
+        failed.join("\n")
+    );
+}